@@ -1,95 +1,745 @@
-use std::collections::linked_list::Iter;
-use std::collections::LinkedList;
+use std::any::Any;
+use std::cmp::Ordering;
+use std::collections::VecDeque;
 use std::fmt;
+use std::io::{self, Read, Write};
+use std::ops::RangeInclusive;
+use std::str::FromStr;
 use std::ops::Div;
 use num_bigint::BigInt;
 use core::ops::Rem;
 use num_traits::pow::Pow;
-use num_traits::identities::{Zero, One};
+use num_traits::identities::Zero;
+use num_traits::sign::Signed;
 use num_traits::ToPrimitive;
+use im_rc::vector::Iter;
+use im_rc::{HashMap, Vector};
+use crate::debug::Debugger;
+use crate::input::{Input, Stdin};
+use crate::journal::{Entry, Journal};
+use crate::output::{Buffer, Output, Stdout};
+use crate::token::Op;
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+/// The number of states kept for `undo`.
+const UNDO_LIMIT: usize = 1000;
+/// The most values a stack may hold inline, without a shared identity to compare.
+const INLINE_VALUES: usize = 4;
+
+/// The radices `i` accepts, as in GNU dc.
+const INPUT_RADIXES: RangeInclusive<u8> = 2..=16;
+/// The radices `o` accepts.
+const OUTPUT_RADIXES: RangeInclusive<u8> = 2..=u8::MAX;
+
+/// The first line of a session file, followed by the version of the format.
+const SESSION_HEADER: &str = "dc session";
+const SESSION_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum OpResult {
     Ok,
     Exit,
+    /// The operation asks the interpreter to run the given string as a macro.
+    Macro(String),
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum StackValue {
     Number(BigInt),
+    String(String),
+}
+
+impl StackValue {
+    pub fn as_number(&self) -> Option<&BigInt> {
+        match self {
+            StackValue::Number(num) => Some(num),
+            StackValue::String(_) => None,
+        }
+    }
+
+    pub fn as_string(&self) -> Option<&str> {
+        match self {
+            StackValue::Number(_) => None,
+            StackValue::String(string) => Some(string),
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            StackValue::Number(_) => "number",
+            StackValue::String(_) => "string",
+        }
+    }
+
+    /// Returns the value as listings show it: numbers as they are, strings in brackets.
+    pub fn literal(&self) -> String {
+        match self {
+            StackValue::Number(num) => num.to_string(),
+            StackValue::String(string) => format!("[{}]", string),
+        }
+    }
+
+    /// Returns the number of decimal digits of a number.
+    pub fn digits(&self) -> Option<usize> {
+        match self {
+            StackValue::Number(x) => Some(x.abs().to_string().len()),
+            StackValue::String(_) => None,
+        }
+    }
+
+    /// Returns the number of fraction digits of a number, which is always zero as numbers are
+    /// integers.
+    pub fn scale(&self) -> Option<usize> {
+        match self {
+            StackValue::Number(_) => Some(0),
+            StackValue::String(_) => None,
+        }
+    }
 }
 
 impl fmt::Display for StackValue {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             StackValue::Number(x) => write!(f, "{}", x),
+            StackValue::String(x) => write!(f, "{}", x),
         }
     }
 }
 
+/// An error that occurred while running some input.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    /// The name of the script, if the input came from one.
+    pub source: Option<String>,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.source {
+            Some(source) => write!(f, "{}:{}: {}", source, self.line, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// The flavour of dc to emulate.
+///
+/// `Posix` only accepts the traditional commands, `Gnu` adds the GNU extensions (`n`, `r`, `R`,
+/// `~`, `|` and the negated comparisons `!<`, `!>` and `!=`), and both report stack underflow the
+/// way GNU dc does. `Posix` rejects the negated comparisons, and in both any other `!` runs the
+/// rest of the line as a shell command. `Extended` accepts everything this implementation
+/// supports except shell commands, including `t` to drop the top of the stack, and treats `d`
+/// and `r` on a too short stack as no-ops.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum Dialect {
+    Posix,
+    Gnu,
+    #[default]
+    Extended,
+}
+
+impl Dialect {
+    /// Returns whether the given command character is part of this dialect.
+    pub fn accepts(self, command: char) -> bool {
+        match self {
+            Dialect::Posix => !matches!(command, 'n' | 'r' | 'R' | '~' | '|' | 't'),
+            Dialect::Gnu => command != 't',
+            Dialect::Extended => true,
+        }
+    }
+
+    /// Returns whether operations on a too short stack should fail instead of doing nothing.
+    pub fn is_strict(self) -> bool {
+        self != Dialect::Extended
+    }
+}
+
+impl fmt::Display for Dialect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Dialect::Posix => write!(f, "posix"),
+            Dialect::Gnu => write!(f, "gnu"),
+            Dialect::Extended => write!(f, "extended"),
+        }
+    }
+}
+
+impl FromStr for Dialect {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "posix" => Ok(Dialect::Posix),
+            "gnu" => Ok(Dialect::Gnu),
+            "extended" => Ok(Dialect::Extended),
+            _ => Err(format!("unknown dialect: {}", s)),
+        }
+    }
+}
+
+/// Selects the operations that are logged to the output, stderr by default, while tracing.
+/// Without filters every operation is logged.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Trace {
+    /// Only log operations run inside the macros of these registers, if any are given.
+    pub registers: Vec<char>,
+    /// Only log operations up to this macro depth, the input itself being depth 0.
+    pub max_depth: Option<usize>,
+}
+
+/// The values and settings of a calculator, saved by `checkpoint`. The stack and the registers
+/// are persistent collections sharing their structure with the calculator, so snapshots are
+/// cheap to take.
+#[derive(Clone, PartialEq)]
+struct Snapshot {
+    stack: Vector<StackValue>,
+    registers: HashMap<char, Vector<StackValue>>,
+    arrays: HashMap<char, HashMap<usize, StackValue>>,
+    input_radix: u8,
+    output_radix: u8,
+    precision: u8,
+}
+
+impl Snapshot {
+    /// Tells whether nothing changed between the two snapshots. The collections are compared by
+    /// identity, which clones share until they are modified, so the values are never walked. Only
+    /// a stack of a few values, which has no identity as it is stored inline, is compared by value.
+    fn same(&self, other: &Snapshot) -> bool {
+        let small = self.stack.len() <= INLINE_VALUES && other.stack.len() <= INLINE_VALUES;
+        (self.stack.ptr_eq(&other.stack) || (small && self.stack == other.stack))
+            && self.registers.ptr_eq(&other.registers)
+            && self.arrays.ptr_eq(&other.arrays)
+            && (self.input_radix, self.output_radix, self.precision)
+                == (other.input_radix, other.output_radix, other.precision)
+    }
+}
+
 pub struct Calculator {
-    stack: LinkedList<StackValue>,
+    stack: Vector<StackValue>,
+    registers: HashMap<char, Vector<StackValue>>,
+    arrays: HashMap<char, HashMap<usize, StackValue>>,
+    undo: VecDeque<Snapshot>,
+    redo: Vec<Snapshot>,
+    dialect: Dialect,
+    trace: Option<Trace>,
+    output: Box<dyn Output>,
+    journal: Option<Journal>,
+    debugger: Option<Box<dyn Debugger>>,
+    /// Where `?` reads its lines from.
+    input: Box<dyn Input>,
     input_radix: u8,
     output_radix: u8,
     precision: u8,
+    /// The fraction digits of fixed-point numbers, see `set_scale`.
+    scale: u32,
+}
+
+impl Default for Calculator {
+    fn default() -> Self {
+        Calculator::new()
+    }
 }
 
 impl Calculator {
+    /// Creates a calculator with an empty stack and registers, in the extended dialect. Like the
+    /// standalone dc it prints to stdout and stderr, and `?` reads stdin: see `set_output` and
+    /// `set_input` to change that.
     pub fn new() -> Calculator {
         Calculator {
-            stack: LinkedList::new(),
+            stack: Vector::new(),
+            registers: HashMap::new(),
+            arrays: HashMap::new(),
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            dialect: Dialect::default(),
+            trace: None,
+            output: Box::new(Stdout),
+            journal: None,
+            debugger: None,
+            input: Box::new(Stdin),
             input_radix: 10,
             output_radix: 10,
             precision: 0,
+            scale: 0,
+        }
+    }
+
+    /// Runs dc source, the same way as the standalone calculator does. Errors of the commands
+    /// are reported, see `capture_output`, and the following commands still run. `Exit` is
+    /// returned if the input quits with `q`.
+    ///
+    /// ```
+    /// use dc::prelude::*;
+    ///
+    /// let mut calculator = Calculator::new();
+    /// calculator.capture_output();
+    /// calculator.eval("[d*]sq 7 lqx p").unwrap();
+    /// assert_eq!(calculator.output(), ["49\n"]);
+    /// assert_eq!(calculator.eval("q"), Ok(OpResult::Exit));
+    /// ```
+    pub fn eval(&mut self, source: &str) -> Result<OpResult, String> {
+        crate::process_input(self, source)
+    }
+
+    /// Runs a single operation, including the printing commands and any macro it runs. Unlike
+    /// `eval`, the error of the operation is returned instead of being reported.
+    ///
+    /// ```
+    /// use dc::prelude::*;
+    ///
+    /// let mut calculator = Calculator::new();
+    /// calculator.execute(&Op::Push(6.into())).unwrap();
+    /// calculator.execute(&Op::PushString("7*".to_owned())).unwrap();
+    /// calculator.execute(&Op::Execute).unwrap();
+    /// assert_eq!(calculator.peek().and_then(StackValue::as_number), Some(&42.into()));
+    /// assert_eq!(calculator.execute(&Op::Add), Err("stack empty!".to_owned()));
+    /// ```
+    pub fn execute(&mut self, op: &Op) -> Result<OpResult, String> {
+        crate::execute_op(self, op)
+    }
+
+    /// Empties the stack and the registers, and restores the default radices and precision. The
+    /// dialect, scale, trace and output settings are kept.
+    pub fn reset(&mut self) {
+        self.stack.clear();
+        self.registers.clear();
+        self.arrays.clear();
+        self.input_radix = 10;
+        self.output_radix = 10;
+        self.precision = 0;
+    }
+
+    /// Saves the stack, the registers and the settings, so `undo` can return to this state. The
+    /// REPL takes a checkpoint before every line. Undone states can no longer be redone.
+    pub fn checkpoint(&mut self) {
+        if self.undo.len() == UNDO_LIMIT {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(self.snapshot());
+        self.redo.clear();
+    }
+
+    /// Returns to the last checkpoint that differs from the current state. Returns false if
+    /// there is none.
+    pub fn undo(&mut self) -> bool {
+        let current = self.snapshot();
+        while let Some(snapshot) = self.undo.pop_back() {
+            if !snapshot.same(&current) {
+                self.redo.push(current);
+                self.restore(snapshot);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Reverts the last `undo`. Returns false if there is nothing to redo.
+    pub fn redo(&mut self) -> bool {
+        match self.redo.pop() {
+            Some(snapshot) => {
+                self.undo.push_back(self.snapshot());
+                self.restore(snapshot);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            stack: self.stack.clone(),
+            registers: self.registers.clone(),
+            arrays: self.arrays.clone(),
+            input_radix: self.input_radix,
+            output_radix: self.output_radix,
+            precision: self.precision,
         }
     }
 
+    fn restore(&mut self, snapshot: Snapshot) {
+        self.stack = snapshot.stack;
+        self.registers = snapshot.registers;
+        self.arrays = snapshot.arrays;
+        self.input_radix = snapshot.input_radix;
+        self.output_radix = snapshot.output_radix;
+        self.precision = snapshot.precision;
+    }
+
+    /// Writes the stack, the registers and the settings in the versioned session format. After
+    /// the `dc session 1` header every line holds one entry:
+    ///
+    /// ```text
+    /// input_radix 10
+    /// output_radix 10
+    /// precision 0
+    /// stack number 0 -42
+    /// register 97 string [d*]
+    /// array 97 3 number 0 5
+    /// ```
+    ///
+    /// Stack and register values are listed from the bottom. Registers are given by their
+    /// character code, array elements by their index too. Numbers have their scale before their
+    /// digits, strings escape backslashes and line breaks.
+    pub fn save_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "{} {}", SESSION_HEADER, SESSION_VERSION)?;
+        writeln!(writer, "input_radix {}", self.input_radix)?;
+        writeln!(writer, "output_radix {}", self.output_radix)?;
+        writeln!(writer, "precision {}", self.precision)?;
+        for value in &self.stack {
+            writeln!(writer, "stack {}", session_value(value))?;
+        }
+
+        let mut registers = self.registers.keys().chain(self.arrays.keys()).copied().collect::<Vec<char>>();
+        registers.sort_unstable();
+        registers.dedup();
+        for register in registers {
+            for value in self.registers.get(&register).into_iter().flatten() {
+                writeln!(writer, "register {} {}", register as u32, session_value(value))?;
+            }
+            let mut elements = self.arrays.get(&register).into_iter().flatten().collect::<Vec<_>>();
+            elements.sort_unstable_by_key(|(index, _)| **index);
+            for (index, value) in elements {
+                writeln!(writer, "array {} {} {}", register as u32, index, session_value(value))?;
+            }
+        }
+
+        writer.flush()
+    }
+
+    /// Replaces the stack, the registers and the settings with a session written by `save_to`.
+    /// Nothing changes if the session is invalid.
+    pub fn load_from<R: Read>(&mut self, mut reader: R) -> io::Result<()> {
+        let mut content = String::new();
+        reader.read_to_string(&mut content)?;
+        let invalid = |line: usize, message: String| io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", line, message));
+
+        let mut lines = content.lines().enumerate().map(|(index, line)| (index + 1, line));
+        match lines.next().and_then(|(_, header)| header.strip_prefix(SESSION_HEADER)) {
+            Some(version) if version.trim() == SESSION_VERSION.to_string() => (),
+            Some(version) => return Err(invalid(1, format!("unsupported session version: {}", version.trim()))),
+            None => return Err(invalid(1, "not a dc session".to_owned())),
+        }
+
+        let mut session = Calculator::new().snapshot();
+        for (number, line) in lines {
+            let (kind, rest) = line.split_once(' ').unwrap_or((line, ""));
+            let entry = match kind {
+                "input_radix" => parse_setting(rest, INPUT_RADIXES).map(|radix| session.input_radix = radix),
+                "output_radix" => parse_setting(rest, OUTPUT_RADIXES).map(|radix| session.output_radix = radix),
+                "precision" => parse_setting(rest, 0..=u8::MAX).map(|precision| session.precision = precision),
+                "stack" => parse_session_value(rest).map(|value| session.stack.push_back(value)),
+                "register" => parse_register(rest).and_then(|(register, rest)| {
+                    let value = parse_session_value(rest)?;
+                    session.registers.entry(register).or_default().push_back(value);
+                    Ok(())
+                }),
+                "array" => parse_register(rest).and_then(|(register, rest)| {
+                    let (index, rest) = rest.split_once(' ').ok_or_else(|| "missing array value".to_owned())?;
+                    let index = index.parse::<usize>().map_err(|_| format!("invalid array index: {}", index))?;
+                    let value = parse_session_value(rest)?;
+                    session.arrays.entry(register).or_default().insert(index, value);
+                    Ok(())
+                }),
+                "" => Ok(()),
+                _ => Err(format!("unknown entry: {}", kind)),
+            };
+            entry.map_err(|err| invalid(number, err))?;
+        }
+
+        self.restore(session);
+        Ok(())
+    }
+
+    pub fn dialect(&self) -> Dialect {
+        self.dialect
+    }
+
+    pub fn set_dialect(&mut self, dialect: Dialect) {
+        self.dialect = dialect;
+    }
+
+    pub fn input_radix(&self) -> u8 {
+        self.input_radix
+    }
+
+    pub fn output_radix(&self) -> u8 {
+        self.output_radix
+    }
+
+    pub fn precision(&self) -> u8 {
+        self.precision
+    }
+
+    /// Sets the radix numbers are read in, like `i`. It has to be between 2 and 16.
+    pub fn set_input_radix(&mut self, radix: u8) -> Result<(), String> {
+        if !INPUT_RADIXES.contains(&radix) {
+            return Err(format!("input radix out of range: {}", radix));
+        }
+        self.input_radix = radix;
+        Ok(())
+    }
+
+    /// Sets the radix numbers are printed in, like `o`. It has to be at least 2.
+    pub fn set_output_radix(&mut self, radix: u8) -> Result<(), String> {
+        if !OUTPUT_RADIXES.contains(&radix) {
+            return Err(format!("output radix out of range: {}", radix));
+        }
+        self.output_radix = radix;
+        Ok(())
+    }
+
+    pub fn set_precision(&mut self, precision: u8) {
+        self.precision = precision;
+    }
+
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
+    /// Makes numbers fixed-point decimals with `scale` fraction digits, kept as whole numbers of
+    /// units of 10^-scale: with scale 2 the literal `1` is stored as 100. `*`, `/`, `%`, `~`, `^`
+    /// and `v` keep their results at the scale like dc does with `k` set to it, truncating the
+    /// digits beyond. Values already stored are not converted.
+    ///
+    /// ```
+    /// use dc::prelude::*;
+    ///
+    /// let mut calculator = Calculator::new();
+    /// calculator.capture_output();
+    /// calculator.set_scale(2);
+    /// calculator.eval("2 3 / p 5 v p").unwrap();
+    /// assert_eq!(calculator.output(), ["0.66\n", "2.23\n"]);
+    /// ```
+    pub fn set_scale(&mut self, scale: u32) {
+        self.scale = scale;
+    }
+
+    /// Returns a whole number as a value at the calculator's scale, like a literal pushes it.
+    pub fn number(&self, num: BigInt) -> StackValue {
+        StackValue::Number(self.scaled(num))
+    }
+
+    /// Returns a value the way the printing commands write it, numbers with the fraction digits
+    /// of the scale.
+    pub fn format(&self, value: &StackValue) -> String {
+        match value {
+            StackValue::Number(num) if self.scale > 0 => format_decimal(num, self.scale as usize),
+            value => value.to_string(),
+        }
+    }
+
+    /// Returns the top of the given register's stack, the value `l` would push.
+    ///
+    /// ```
+    /// use dc::prelude::*;
+    ///
+    /// let mut calculator = Calculator::new();
+    /// calculator.eval("42 sa").unwrap();
+    /// assert_eq!(calculator.register('a').and_then(StackValue::as_number), Some(&42.into()));
+    /// assert_eq!(calculator.register('b'), None);
+    /// ```
+    pub fn register(&self, register: char) -> Option<&StackValue> {
+        self.registers.get(&register).and_then(|stack| stack.back())
+    }
+
+    /// Replaces the top of the given register's stack, like `s`.
+    pub fn set_register(&mut self, register: char, value: StackValue) {
+        let stack = self.registers.entry(register).or_default();
+        stack.pop_back();
+        stack.push_back(value);
+    }
+
+    /// Returns an element of the given register's array, `None` if it was never set.
+    pub fn array_value(&self, register: char, index: usize) -> Option<&StackValue> {
+        self.arrays.get(&register).and_then(|array| array.get(&index))
+    }
+
+    /// Returns the names of the registers that hold values on their stack or in their array.
+    pub fn register_names(&self) -> Vec<char> {
+        let mut names = self.registers
+            .iter()
+            .filter(|(_, stack)| !stack.is_empty())
+            .map(|(name, _)| *name)
+            .chain(self.arrays.iter().filter(|(_, array)| !array.is_empty()).map(|(name, _)| *name))
+            .collect::<Vec<char>>();
+        names.sort_unstable();
+        names.dedup();
+        names
+    }
+
+    /// Returns the stack of the given register, the top value last.
+    pub fn register_stack(&self, register: char) -> Vec<&StackValue> {
+        self.registers.get(&register).map_or(Vec::new(), |stack| stack.iter().collect())
+    }
+
+    /// Returns the elements of the given register's array that were set, ordered by index.
+    pub fn register_array(&self, register: char) -> Vec<(usize, &StackValue)> {
+        let mut elements = self.arrays
+            .get(&register)
+            .map(|array| array.iter().map(|(index, value)| (*index, value)).collect::<Vec<(usize, &StackValue)>>())
+            .unwrap_or_default();
+        elements.sort_unstable_by_key(|(index, _)| *index);
+        elements
+    }
+
+    pub fn trace(&self) -> Option<&Trace> {
+        self.trace.as_ref()
+    }
+
+    pub fn set_trace(&mut self, trace: Option<Trace>) {
+        self.trace = trace;
+    }
+
+    /// Sets where the printing commands, error reports and traces write, stdout and stderr by
+    /// default.
+    ///
+    /// ```
+    /// use dc::output::Writer;
+    /// use dc::prelude::*;
+    ///
+    /// let mut calculator = Calculator::new();
+    /// calculator.set_output(Box::new(Writer::new(std::io::sink())));
+    /// calculator.eval("1p").unwrap();
+    /// ```
+    pub fn set_output(&mut self, output: Box<dyn Output>) {
+        self.output = output;
+    }
+
+    /// Makes printing commands and error reports collect their text in a `Buffer`, available from
+    /// `output` and `errors`, instead of writing it to stdout and stderr.
+    pub fn capture_output(&mut self) {
+        self.set_output(Box::new(Buffer::default()));
+    }
+
+    /// Returns the output if it is a `T`, to read what a `Buffer` or a writer kept.
+    pub fn output_as<T: Output>(&self) -> Option<&T> {
+        let output: &dyn Any = &*self.output;
+        output.downcast_ref()
+    }
+
+    /// Returns the text printed so far, one string per command, if the output is a `Buffer`.
+    pub fn output(&self) -> &[String] {
+        self.output_as::<Buffer>().map_or(&[], Buffer::printed)
+    }
+
+    /// Returns the errors reported so far, if the output is a `Buffer`.
+    pub fn errors(&self) -> &[Diagnostic] {
+        self.output_as::<Buffer>().map_or(&[], Buffer::reported)
+    }
+
+    /// Starts keeping a journal of the session: the lines read by `?` and the printed text are
+    /// added by the calculator, the input chunks by whoever runs them, with `record_entry`.
+    pub fn record(&mut self) {
+        self.journal = Some(Journal::default());
+    }
+
+    pub fn journal(&self) -> Option<&Journal> {
+        self.journal.as_ref()
+    }
+
+    /// Makes the interpreter call the debugger before every operation.
+    pub fn set_debugger(&mut self, debugger: Option<Box<dyn Debugger>>) {
+        self.debugger = debugger;
+    }
+
+    /// Removes the debugger while the interpreter runs, so it can be given the calculator.
+    pub(crate) fn take_debugger(&mut self) -> Option<Box<dyn Debugger>> {
+        self.debugger.take()
+    }
+
+    /// Adds an entry to the journal, if one is being recorded.
+    pub fn record_entry(&mut self, entry: Entry) {
+        if let Some(journal) = &mut self.journal {
+            journal.entries.push(entry);
+        }
+    }
+
+    /// Sets where `?` reads its lines from, stdin by default.
+    pub fn set_input(&mut self, input: Box<dyn Input>) {
+        self.input = input;
+    }
+
+    /// Reads a line for `?`, without its line break. Returns `None` at the end of the input, and
+    /// a failing read as the error of the command.
+    pub fn read_line(&mut self) -> Result<Option<String>, String> {
+        let line = self.input.read_line().map_err(|err| format!("could not read input: {}", err))?;
+        if let Some(line) = &line {
+            self.record_entry(Entry::Read(line.clone()));
+        }
+        Ok(line)
+    }
+
+    /// Writes text of a printing command to the output. A failing write, such as a broken pipe,
+    /// is returned, and ends the run when a printing command fails.
+    pub fn print(&mut self, text: String) -> Result<(), String> {
+        self.record_entry(Entry::Output(text.clone()));
+        self.output.print(&text).map_err(|err| format!("could not write output: {}", err))
+    }
+
+    /// Writes an error to the output. If even that fails there is nowhere left to tell, so the
+    /// error is dropped.
+    pub fn report(&mut self, diagnostic: Diagnostic) {
+        self.output.report(&diagnostic).ok();
+    }
+
+    /// Writes a trace line to the output. Like errors, a trace that cannot be written is dropped.
+    pub fn log_trace(&mut self, line: &str) {
+        self.output.trace(line).ok();
+    }
+
+    #[deprecated(since = "0.2.0", note = "renamed to `push_input_radix`")]
     pub fn get_input_radix(&mut self) -> Result<OpResult, String> {
-        self.stack
-            .push_back(StackValue::Number(BigInt::from(self.input_radix.clone())));
-        Ok(OpResult::Ok)
+        self.push_input_radix()
     }
 
+    #[deprecated(since = "0.2.0", note = "renamed to `push_output_radix`")]
     pub fn get_output_radix(&mut self) -> Result<OpResult, String> {
-        self.stack
-            .push_back(StackValue::Number(BigInt::from(self.output_radix.clone())));
-        Ok(OpResult::Ok)
+        self.push_output_radix()
     }
 
+    #[deprecated(since = "0.2.0", note = "renamed to `push_precision`")]
     pub fn get_precision(&mut self) -> Result<OpResult, String> {
-        self.stack
-            .push_back(StackValue::Number(BigInt::from(self.precision.clone())));
-        Ok(OpResult::Ok)
+        self.push_precision()
     }
 
-    pub fn set_input_radix(&mut self) -> Result<OpResult, String> {
-        self.arg1_f64()
-            .map(|value| {
-                // TODO error handling
-                self.input_radix = value.to_u8().unwrap();
-            })
-            .map(|_| OpResult::Ok)
+    pub fn push_input_radix(&mut self) -> Result<OpResult, String> {
+        self.push(self.number(BigInt::from(self.input_radix)))
     }
 
-    pub fn set_output_radix(&mut self) -> Result<OpResult, String> {
-        self.arg1_f64()
-            .map(|value| {
-                // TODO error handling
-                self.output_radix = value.to_u8().unwrap();
-            })
-            .map(|_| OpResult::Ok)
+    pub fn push_output_radix(&mut self) -> Result<OpResult, String> {
+        self.push(self.number(BigInt::from(self.output_radix)))
     }
 
-    pub fn set_precision(&mut self) -> Result<OpResult, String> {
-        self.arg1_f64()
-            .map(|value| {
-                // TODO error handling
-                self.precision = value.to_u8().unwrap();
-            })
-            .map(|_| OpResult::Ok)
+    pub fn push_precision(&mut self) -> Result<OpResult, String> {
+        self.push(self.number(BigInt::from(self.precision)))
     }
 
-    pub fn iter(&self) -> Iter<StackValue> {
+    pub fn pop_input_radix(&mut self) -> Result<OpResult, String> {
+        self.arg1_u8("input radix")
+            .and_then(|value| self.set_input_radix(value))
+            .map(|_| self.discard(1))
+    }
+
+    pub fn pop_output_radix(&mut self) -> Result<OpResult, String> {
+        self.arg1_u8("output radix")
+            .and_then(|value| self.set_output_radix(value))
+            .map(|_| self.discard(1))
+    }
+
+    pub fn pop_precision(&mut self) -> Result<OpResult, String> {
+        self.arg1_u8("precision")
+            .map(|value| self.precision = value)
+            .map(|_| self.discard(1))
+    }
+
+    pub fn stack_depth(&mut self) -> Result<OpResult, String> {
+        let depth = self.stack.len();
+        self.push(self.number(BigInt::from(depth)))
+    }
+
+    pub fn iter(&self) -> Iter<'_, StackValue> {
         self.stack.iter()
     }
 
@@ -118,6 +768,34 @@ impl Calculator {
 
             self.stack.push_back(first);
             self.stack.push_back(second);
+        } else if self.dialect.is_strict() {
+            return Err("stack empty!".to_owned());
+        }
+
+        Ok(OpResult::Ok)
+    }
+
+    /// Pops `n` and cyclically rotates the top `|n|` values: a positive `n` brings the `n`-th
+    /// value to the top, a negative one moves the top value down to the `n`-th position.
+    pub fn rotate(&mut self) -> Result<OpResult, String> {
+        let n = self.arg1_f64()
+            .and_then(|n| n.to_i64().ok_or_else(|| format!("rotation out of range: {}", n)))?;
+        let count = n.unsigned_abs() as usize;
+        if count > self.stack.len() - 1 {
+            return Err("stack empty!".to_owned());
+        }
+
+        self.discard(1);
+        if count > 1 {
+            let mut top = self.stack.split_off(self.stack.len() - count);
+            if n > 0 {
+                let value = top.pop_front().unwrap();
+                top.push_back(value);
+            } else {
+                let value = top.pop_back().unwrap();
+                top.push_front(value);
+            }
+            self.stack.append(top);
         }
 
         Ok(OpResult::Ok)
@@ -125,128 +803,343 @@ impl Calculator {
 
     pub fn add(&mut self) -> Result<OpResult, String> {
         self.arg2_f64()
-            .and_then(|(x, y)| self.push(StackValue::Number(x + y)))
+            .map(|(x, y)| x + y)
+            .and_then(|result| self.replace(2, vec![result]))
     }
 
     pub fn sub(&mut self) -> Result<OpResult, String> {
         self.arg2_f64()
-            .and_then(|(x, y)| self.push(StackValue::Number(x - y)))
+            .map(|(x, y)| x - y)
+            .and_then(|result| self.replace(2, vec![result]))
     }
 
     pub fn mul(&mut self) -> Result<OpResult, String> {
         self.arg2_f64()
-            .and_then(|(x, y)| self.push(StackValue::Number(x * y)))
+            .map(|(x, y)| (x * y).div(self.unit()))
+            .and_then(|result| self.replace(2, vec![result]))
     }
 
     pub fn div(&mut self) -> Result<OpResult, String> {
         self.arg2_f64()
-            .and_then(|(x, y)| {
-                if y == BigInt::from(0) {
-                    Err("division by zero".to_owned())
-                } else {
-                    Ok((x, y))
-                }
-            })
-            .and_then(|(x, y)| self.push(StackValue::Number(x / y)))
+            .and_then(non_zero_divisor)
+            .map(|(x, y)| self.div_rem_scaled(x, y).0)
+            .and_then(|result| self.replace(2, vec![result]))
     }
 
     pub fn modulo(&mut self) -> Result<OpResult, String> {
         self.arg2_f64()
-            .and_then(|(x, y)| self.push(StackValue::Number(x % y)))
+            .and_then(non_zero_divisor)
+            .map(|(x, y)| self.div_rem_scaled(x, y).1)
+            .and_then(|result| self.replace(2, vec![result]))
     }
 
     pub fn div_rem(&mut self) -> Result<OpResult, String> {
         self.arg2_f64()
-            .and_then(|(x, y)| {
-                if y == BigInt::from(0) {
-                    Err("division by zero".to_owned())
-                } else {
-                    Ok((x, y))
-                }
-            })
-            .and_then(|(x, y)| {
-                let div = x.clone().div(&y);
-                let rem = x.clone().rem(&y);
-                self.push(StackValue::Number(rem))?;
-                self.push(StackValue::Number(div))
-            })
+            .and_then(non_zero_divisor)
+            .map(|(x, y)| self.div_rem_scaled(x, y))
+            .and_then(|(div, rem)| self.replace(2, vec![rem, div]))
     }
 
     pub fn exp(&mut self) -> Result<OpResult, String> {
-        // TODO error handling
         self.arg2_f64()
-            .and_then(|(x, y)| self.push(StackValue::Number(x.pow(&y.to_biguint().unwrap()))))
+            .and_then(|(x, y)| {
+                let exponent = self.whole(y).to_biguint().ok_or_else(|| "negative exponent".to_owned())?;
+                if exponent.is_zero() {
+                    Ok(self.unit())
+                } else {
+                    Ok(x.pow(&exponent).div(self.unit().pow(exponent - 1u32)))
+                }
+            })
+            .and_then(|result| self.replace(2, vec![result]))
     }
 
     pub fn sqrt(&mut self) -> Result<OpResult, String> {
         self.arg1_f64()
-            .and_then(|x| self.push(StackValue::Number(x.sqrt())))
+            .and_then(|x| {
+                if x.is_negative() {
+                    Err("square root of negative number".to_owned())
+                } else {
+                    Ok((x * self.unit()).sqrt())
+                }
+            })
+            .and_then(|result| self.replace(1, vec![result]))
     }
 
+    /// Works on the whole parts of the numbers, as it is only defined for integers.
     pub fn mod_exp(&mut self) -> Result<OpResult, String> {
         self.arg3_f64()
             .and_then(|(base, exponent, modulus)| {
-                // TODO
-                // if modulus <= BigInt::from(0) || modulus != modulus.trunc() {
-                //     Err("base must be non-zero and an integer".to_owned())
-                // } else if exponent < BigInt::from(0) {
-                //     Err("exponent must be non-negative and an integer".to_owned())
-                // } else if base != base.trunc() {
-                //     Err("modulus must be an integer".to_owned())
-                // } else {
-                //     Ok((base, exponent, modulus))
-                // }
-
-                Ok((base, exponent, modulus))
-            })
-            .and_then(|(base, exponent, modulus)| {
-                if modulus == BigInt::one() {
-                    self.push(StackValue::Number(BigInt::zero()))
+                let (base, exponent, modulus) = (self.whole(base), self.whole(exponent), self.whole(modulus));
+                if modulus.is_zero() {
+                    Err("modulus must be non-zero".to_owned())
+                } else if exponent.is_negative() {
+                    Err("exponent must be non-negative".to_owned())
                 } else {
-                    let mut c = 1;
-                    // TODO error handling
-                    let base = base.to_u64().unwrap();
-                    let exponent = exponent.to_u64().unwrap();
-                    let modulus = modulus.to_u64().unwrap();
-                    for _ in 0..exponent.to_u64().unwrap() {
-                        c = (c * base) % modulus;
-                    }
-                    self.push(StackValue::Number(BigInt::from(c)))
+                    Ok(self.scaled(base.modpow(&exponent, &modulus)))
                 }
             })
+            .and_then(|result| self.replace(3, vec![result]))
     }
 
-    fn arg1_f64(&mut self) -> Result<BigInt, String> {
-        if self.stack.len() >= 1 {
-            let StackValue::Number(x) = self.stack.pop_back().unwrap();
-            Ok(x)
-        } else {
-            Err("stack empty!".to_owned())
+    pub fn store(&mut self, register: char) -> Result<OpResult, String> {
+        let value = self.pop().ok_or_else(|| "stack empty!".to_owned())?;
+        let stack = self.registers.entry(register).or_default();
+        stack.pop_back();
+        stack.push_back(value);
+        Ok(OpResult::Ok)
+    }
+
+    pub fn load(&mut self, register: char) -> Result<OpResult, String> {
+        let value = self.register_top(register)?;
+        self.push(value)
+    }
+
+    pub fn push_register(&mut self, register: char) -> Result<OpResult, String> {
+        let value = self.pop().ok_or_else(|| "stack empty!".to_owned())?;
+        self.registers.entry(register).or_default().push_back(value);
+        Ok(OpResult::Ok)
+    }
+
+    pub fn pop_register(&mut self, register: char) -> Result<OpResult, String> {
+        let value = self.registers
+            .get_mut(&register)
+            .and_then(|stack| stack.pop_back())
+            .ok_or_else(|| format!("register {} is empty", register))?;
+        self.push(value)
+    }
+
+    /// Pops an index and a value, and stores the value in the array of the given register.
+    pub fn store_array(&mut self, register: char) -> Result<OpResult, String> {
+        if self.stack.len() < 2 {
+            return Err("stack empty!".to_owned());
         }
+
+        let index = self.arg1_index()?;
+        self.discard(1);
+        let value = self.pop().unwrap();
+        self.set_array_value(register, index, value);
+        Ok(OpResult::Ok)
     }
 
-    fn arg2_f64(&mut self) -> Result<(BigInt, BigInt), String> {
-        if self.stack.len() >= 2 {
-            let StackValue::Number(y) = self.stack.pop_back().unwrap();
-            let StackValue::Number(x) = self.stack.pop_back().unwrap();
-            Ok((x, y))
+    /// Pops an index and pushes the matching element of the given register's array, or zero if
+    /// the element was never set.
+    pub fn load_array(&mut self, register: char) -> Result<OpResult, String> {
+        let index = self.arg1_index()?;
+        let value = self.arrays
+            .get(&register)
+            .and_then(|array| array.get(&index))
+            .cloned()
+            .unwrap_or_else(|| StackValue::Number(BigInt::zero()));
+        self.discard(1);
+        self.push(value)
+    }
+
+    pub fn set_array_value(&mut self, register: char, index: usize, value: StackValue) {
+        self.arrays.entry(register).or_default().insert(index, value);
+    }
+
+    pub fn clear_array(&mut self, register: char) {
+        self.arrays.remove(&register);
+    }
+
+    /// Pops the top of the stack and runs it: strings are returned as a macro to execute,
+    /// numbers are pushed back unchanged.
+    pub fn execute_top(&mut self) -> Result<OpResult, String> {
+        let value = self.pop().ok_or_else(|| "stack empty!".to_owned())?;
+        self.run(value)
+    }
+
+    /// Pops two numbers and, if `condition` holds for the ordering of the original top of the
+    /// stack relative to the one below it, runs the top of the given register like `execute_top`.
+    pub fn execute_if<F>(&mut self, register: char, condition: F) -> Result<OpResult, String>
+    where
+        F: FnOnce(Ordering) -> bool,
+    {
+        let matches = self.arg2_f64().map(|(x, y)| condition(y.cmp(x)))?;
+        let value = if matches {
+            Some(self.register_top(register)?)
         } else {
-            Err("stack empty!".to_owned())
+            None
+        };
+
+        self.discard(2);
+        match value {
+            Some(value) => self.run(value),
+            None => Ok(OpResult::Ok),
         }
     }
 
-    fn arg3_f64(&mut self) -> Result<(BigInt, BigInt, BigInt), String> {
-        if self.stack.len() >= 3 {
-            let StackValue::Number(z) = self.stack.pop_back().unwrap();
-            let StackValue::Number(y) = self.stack.pop_back().unwrap();
-            let StackValue::Number(x) = self.stack.pop_back().unwrap();
-            Ok((x, y, z))
-        } else {
-            Err("stack empty!".to_owned())
+    fn run(&mut self, value: StackValue) -> Result<OpResult, String> {
+        match value {
+            StackValue::String(source) => Ok(OpResult::Macro(source)),
+            number => self.push(number),
+        }
+    }
+
+    fn register_top(&self, register: char) -> Result<StackValue, String> {
+        self.registers
+            .get(&register)
+            .and_then(|stack| stack.back())
+            .cloned()
+            .ok_or_else(|| format!("register {} is empty", register))
+    }
+
+    /// Removes the arguments of a successful operation and pushes its results.
+    fn replace(&mut self, count: usize, results: Vec<BigInt>) -> Result<OpResult, String> {
+        self.discard(count);
+        results.into_iter().for_each(|result| self.stack.push_back(StackValue::Number(result)));
+        Ok(OpResult::Ok)
+    }
+
+    fn discard(&mut self, count: usize) -> OpResult {
+        for _ in 0..count {
+            self.stack.pop_back();
+        }
+        OpResult::Ok
+    }
+
+    // The argument accessors only validate and borrow the operands, the caller removes them
+    // once the whole operation succeeded, so a failing operation leaves the stack untouched.
+
+    fn arg1_index(&self) -> Result<usize, String> {
+        self.arg1_f64()
+            .map(|x| self.whole(x))
+            .and_then(|x| x.to_usize().ok_or_else(|| format!("array index out of range: {}", x)))
+    }
+
+    fn arg1_u8(&self, name: &str) -> Result<u8, String> {
+        self.arg1_f64()
+            .map(|x| self.whole(x))
+            .and_then(|x| x.to_u8().ok_or_else(|| format!("{} out of range: {}", name, x)))
+    }
+
+    fn arg1_f64(&self) -> Result<&BigInt, String> {
+        self.args(1).map(|args| args[0])
+    }
+
+    fn arg2_f64(&self) -> Result<(&BigInt, &BigInt), String> {
+        self.args(2).map(|args| (args[1], args[0]))
+    }
+
+    fn arg3_f64(&self) -> Result<(&BigInt, &BigInt, &BigInt), String> {
+        self.args(3).map(|args| (args[2], args[1], args[0]))
+    }
+
+    /// One at the scale, 10^scale.
+    fn unit(&self) -> BigInt {
+        BigInt::from(10).pow(self.scale)
+    }
+
+    /// Converts a whole number to the scale.
+    fn scaled(&self, num: BigInt) -> BigInt {
+        if self.scale == 0 { num } else { num * self.unit() }
+    }
+
+    /// Returns the whole part of a number at the scale, truncated towards zero.
+    fn whole(&self, num: &BigInt) -> BigInt {
+        if self.scale == 0 { num.clone() } else { num.div(self.unit()) }
+    }
+
+    /// Divides at the scale. The remainder is what is left of `x` after subtracting `y` times
+    /// the quotient, as dc computes it with `k` digits.
+    fn div_rem_scaled(&self, x: &BigInt, y: &BigInt) -> (BigInt, BigInt) {
+        if self.scale == 0 {
+            return (x.div(y), x.rem(y));
+        }
+        let quotient = (x * self.unit()).div(y);
+        let remainder = x - (&quotient * y).div(self.unit());
+        (quotient, remainder)
+    }
+
+    /// Returns the top `count` values of the stack, the topmost first.
+    fn args(&self, count: usize) -> Result<Vec<&BigInt>, String> {
+        if self.stack.len() < count {
+            return Err("stack empty!".to_owned());
         }
+
+        self.stack.iter().rev().take(count).map(number).collect()
+    }
+}
+
+fn number(value: &StackValue) -> Result<&BigInt, String> {
+    match value {
+        StackValue::Number(x) => Ok(x),
+        StackValue::String(_) => Err("non-numeric value".to_owned()),
+    }
+}
+
+fn non_zero_divisor<'a>((x, y): (&'a BigInt, &'a BigInt)) -> Result<(&'a BigInt, &'a BigInt), String> {
+    if y.is_zero() {
+        Err("division by zero".to_owned())
+    } else {
+        Ok((x, y))
     }
 }
 
+/// Writes a whole number of units of 10^-scale as a decimal number.
+fn format_decimal(num: &BigInt, scale: usize) -> String {
+    let digits = format!("{:0>width$}", num.abs().to_string(), width = scale + 1);
+    let (whole, fraction) = digits.split_at(digits.len() - scale);
+    let sign = if num.is_negative() { "-" } else { "" };
+    format!("{}{}.{}", sign, whole, fraction)
+}
+
+fn session_value(value: &StackValue) -> String {
+    match value {
+        StackValue::Number(num) => format!("number 0 {}", num),
+        StackValue::String(string) => {
+            let escaped = string.replace('\\', "\\\\").replace('\n', "\\n").replace('\r', "\\r");
+            format!("string {}", escaped)
+        }
+    }
+}
+
+fn parse_session_value(entry: &str) -> Result<StackValue, String> {
+    match entry.split_once(' ').unwrap_or((entry, "")) {
+        ("number", rest) => {
+            let (scale, digits) = rest.split_once(' ').ok_or_else(|| format!("invalid number: {}", rest))?;
+            if scale != "0" {
+                return Err(format!("unsupported number scale: {}", scale));
+            }
+            BigInt::parse_bytes(digits.as_bytes(), 10)
+                .map(StackValue::Number)
+                .ok_or_else(|| format!("invalid number: {}", digits))
+        }
+        ("string", escaped) => {
+            let mut string = String::with_capacity(escaped.len());
+            let mut chars = escaped.chars();
+            while let Some(ch) = chars.next() {
+                if ch != '\\' {
+                    string.push(ch);
+                    continue;
+                }
+                match chars.next() {
+                    Some('\\') => string.push('\\'),
+                    Some('n') => string.push('\n'),
+                    Some('r') => string.push('\r'),
+                    other => return Err(format!("invalid escape: \\{}", other.map_or(String::new(), String::from))),
+                }
+            }
+            Ok(StackValue::String(string))
+        }
+        (kind, _) => Err(format!("unknown value type: {}", kind)),
+    }
+}
+
+fn parse_register(entry: &str) -> Result<(char, &str), String> {
+    let (code, rest) = entry.split_once(' ').ok_or_else(|| "missing register value".to_owned())?;
+    let register = code.parse::<u32>().ok().and_then(char::from_u32).ok_or_else(|| format!("invalid register: {}", code))?;
+    Ok((register, rest))
+}
+
+fn parse_setting(value: &str, range: RangeInclusive<u8>) -> Result<u8, String> {
+    value
+        .parse::<u8>()
+        .ok()
+        .filter(|value| range.contains(value))
+        .ok_or_else(|| format!("invalid setting: {}", value))
+}
+
 #[cfg(test)]
 mod test {
     use crate::calculator::*;
@@ -312,8 +1205,10 @@ mod test {
         calculator.stack.push_back(StackValue::Number(BigInt::from(3)));
 
         let mut result = Vec::new();
-        for StackValue::Number(i) in calculator.iter() {
-            result.push(i.clone());
+        for value in calculator.iter() {
+            if let StackValue::Number(i) = value {
+                result.push(i.clone());
+            }
         }
 
         assert_eq!(result, vec![BigInt::from(1), BigInt::from(2), BigInt::from(3)]);
@@ -358,6 +1253,116 @@ mod test {
         assert_eq!(*calculator.stack.back().unwrap(), StackValue::Number(BigInt::from(4)));
     }
 
+    #[test]
+    fn test_reverse_singleton_strict() {
+        let mut calculator = Calculator::new();
+        calculator.set_dialect(Dialect::Gnu);
+        calculator.stack.push_back(StackValue::Number(BigInt::from(4)));
+        assert_eq!(calculator.reverse(), Err("stack empty!".to_owned()));
+        assert_eq!(calculator.stack.len(), 1);
+    }
+
+    #[test]
+    fn test_rotate() {
+        let mut calculator = Calculator::new();
+        for i in 1..=4 {
+            calculator.stack.push_back(StackValue::Number(BigInt::from(i)));
+        }
+        calculator.stack.push_back(StackValue::Number(BigInt::from(3)));
+        calculator.rotate().unwrap();
+        let values = calculator.iter().map(|value| value.to_string()).collect::<Vec<String>>();
+        assert_eq!(values, vec!["1", "3", "4", "2"]);
+
+        calculator.stack.push_back(StackValue::Number(BigInt::from(-3)));
+        calculator.rotate().unwrap();
+        let values = calculator.iter().map(|value| value.to_string()).collect::<Vec<String>>();
+        assert_eq!(values, vec!["1", "2", "3", "4"]);
+    }
+
+    #[test]
+    fn test_rotate_too_many() {
+        let mut calculator = Calculator::new();
+        calculator.stack.push_back(StackValue::Number(BigInt::from(1)));
+        calculator.stack.push_back(StackValue::Number(BigInt::from(2)));
+        assert_eq!(calculator.rotate(), Err("stack empty!".to_owned()));
+        assert_eq!(calculator.stack.len(), 2);
+    }
+
+    #[test]
+    fn test_dialect_from_str() {
+        assert_eq!("posix".parse(), Ok(Dialect::Posix));
+        assert_eq!("gnu".parse(), Ok(Dialect::Gnu));
+        assert_eq!("extended".parse(), Ok(Dialect::Extended));
+        assert_eq!("bsd".parse::<Dialect>(), Err("unknown dialect: bsd".to_owned()));
+    }
+
+    #[test]
+    fn test_stack_depth() {
+        let mut calculator = Calculator::new();
+        calculator.stack_depth().unwrap();
+        calculator.stack_depth().unwrap();
+        assert_eq!(*calculator.stack.back().unwrap(), StackValue::Number(BigInt::from(1)));
+    }
+
+    #[test]
+    fn test_arrays() {
+        let mut calculator = Calculator::new();
+        calculator.push(StackValue::String("x".to_owned())).unwrap();
+        calculator.push(StackValue::Number(BigInt::from(3))).unwrap();
+        calculator.store_array('a').unwrap();
+        assert_eq!(calculator.stack.len(), 0);
+
+        calculator.push(StackValue::Number(BigInt::from(3))).unwrap();
+        calculator.load_array('a').unwrap();
+        assert_eq!(calculator.pop(), Some(StackValue::String("x".to_owned())));
+        calculator.push(StackValue::Number(BigInt::from(4))).unwrap();
+        calculator.load_array('a').unwrap();
+        assert_eq!(calculator.pop(), Some(StackValue::Number(BigInt::from(0))));
+    }
+
+    #[test]
+    fn test_array_invalid_index_keeps_stack() {
+        let mut calculator = Calculator::new();
+        calculator.push(StackValue::Number(BigInt::from(1))).unwrap();
+        calculator.push(StackValue::Number(BigInt::from(-1))).unwrap();
+        assert_eq!(calculator.store_array('a'), Err("array index out of range: -1".to_owned()));
+        assert_eq!(calculator.load_array('a'), Err("array index out of range: -1".to_owned()));
+        assert_eq!(calculator.stack.len(), 2);
+    }
+
+    #[test]
+    fn test_register_accessors() {
+        let mut calculator = Calculator::new();
+        calculator.registers.insert('b', Vector::unit(StackValue::Number(BigInt::from(1))));
+        calculator.registers.insert('c', Vector::new());
+        calculator.set_array_value('a', 5, StackValue::Number(BigInt::from(5)));
+        calculator.set_array_value('a', 2, StackValue::Number(BigInt::from(2)));
+        calculator.set_array_value('b', 0, StackValue::Number(BigInt::from(0)));
+        assert_eq!(calculator.register_names(), vec!['a', 'b']);
+        assert_eq!(calculator.register_stack('b'), vec![&StackValue::Number(BigInt::from(1))]);
+        assert_eq!(calculator.register_stack('z'), Vec::<&StackValue>::new());
+        let indices = calculator.register_array('a').iter().map(|(index, _)| *index).collect::<Vec<usize>>();
+        assert_eq!(indices, vec![2, 5]);
+    }
+
+    #[test]
+    fn test_capture_output() {
+        let mut calculator = Calculator::new();
+        calculator.capture_output();
+        calculator.print("1\n".to_owned()).unwrap();
+        calculator.report(Diagnostic { source: None, line: 1, column: 2, message: "oops".to_owned() });
+        assert_eq!(calculator.output(), &["1\n".to_owned()]);
+        assert_eq!(calculator.errors()[0].message, "oops");
+    }
+
+    #[test]
+    fn test_value_info() {
+        let number = StackValue::Number(BigInt::from(-123));
+        let string = StackValue::String("abc".to_owned());
+        assert_eq!((number.type_name(), number.digits(), number.scale()), ("number", Some(3), Some(0)));
+        assert_eq!((string.type_name(), string.digits(), string.scale()), ("string", None, None));
+    }
+
     #[test]
     fn test_add() {
         let mut calculator = Calculator::new();
@@ -454,10 +1459,23 @@ mod test {
         assert_eq!(*calculator.stack.back().unwrap(), StackValue::Number(BigInt::from(445)));
     }
 
+    #[test]
+    fn test_scale() {
+        let mut calculator = Calculator::new();
+        calculator.capture_output();
+        calculator.set_scale(2);
+        crate::process_input(&mut calculator, "7 2% p c 7 2~ f c 150 100/ 3^ p c 2 2 3| p c 5 1:a 3 2/;a p z p").unwrap();
+        assert_eq!(
+            calculator.output(),
+            ["0.00\n", "0.00\n3.50\n", "3.37\n", "1.00\n", "5.00\n", "1.00\n"]
+        );
+        assert_eq!(calculator.format(&StackValue::Number(BigInt::from(-5))), "-0.05");
+    }
+
     #[test]
     fn test_get_input_radix() {
         let mut calculator = Calculator::new();
-        calculator.get_input_radix().unwrap();
+        calculator.push_input_radix().unwrap();
         assert_eq!(calculator.stack.len(), 1);
         assert_eq!(
             *calculator.stack.back().unwrap(),
@@ -468,7 +1486,7 @@ mod test {
     #[test]
     fn test_get_output_radix() {
         let mut calculator = Calculator::new();
-        calculator.get_output_radix().unwrap();
+        calculator.push_output_radix().unwrap();
         assert_eq!(calculator.stack.len(), 1);
         assert_eq!(
             *calculator.stack.back().unwrap(),
@@ -479,7 +1497,7 @@ mod test {
     #[test]
     fn test_get_precision() {
         let mut calculator = Calculator::new();
-        calculator.get_precision().unwrap();
+        calculator.push_precision().unwrap();
         assert_eq!(calculator.stack.len(), 1);
         assert_eq!(
             *calculator.stack.back().unwrap(),
@@ -490,19 +1508,30 @@ mod test {
     #[test]
     fn test_set_input_radix() {
         let mut calculator = Calculator::new();
-        calculator.push(StackValue::Number(BigInt::from(42))).unwrap();
-        calculator.set_input_radix().unwrap();
+        calculator.push(StackValue::Number(BigInt::from(16))).unwrap();
+        calculator.pop_input_radix().unwrap();
         assert_eq!(calculator.stack.len(), 0);
-        assert_eq!(calculator.input_radix, 42);
+        assert_eq!(calculator.input_radix, 16);
         assert_eq!(calculator.output_radix, 10);
         assert_eq!(calculator.precision, 0);
+
+        for radix in [1, 17] {
+            calculator.push(StackValue::Number(BigInt::from(radix))).unwrap();
+            assert_eq!(calculator.pop_input_radix(), Err(format!("input radix out of range: {}", radix)));
+            assert_eq!(calculator.stack.len(), 1);
+            calculator.clear().unwrap();
+        }
+        calculator.push(StackValue::Number(BigInt::from(1))).unwrap();
+        assert!(calculator.pop_output_radix().is_err());
+        assert_eq!(calculator.input_radix, 16);
+        assert_eq!(calculator.output_radix, 10);
     }
 
     #[test]
     fn test_set_output_radix() {
         let mut calculator = Calculator::new();
         calculator.push(StackValue::Number(BigInt::from(42))).unwrap();
-        calculator.set_output_radix().unwrap();
+        calculator.pop_output_radix().unwrap();
         assert_eq!(calculator.stack.len(), 0);
         assert_eq!(calculator.input_radix, 10);
         assert_eq!(calculator.output_radix, 42);
@@ -513,13 +1542,78 @@ mod test {
     fn test_set_precision() {
         let mut calculator = Calculator::new();
         calculator.push(StackValue::Number(BigInt::from(42))).unwrap();
-        calculator.set_precision().unwrap();
+        calculator.pop_precision().unwrap();
         assert_eq!(calculator.stack.len(), 0);
         assert_eq!(calculator.input_radix, 10);
         assert_eq!(calculator.output_radix, 10);
         assert_eq!(calculator.precision, 42);
     }
 
+    #[test]
+    fn test_div_by_zero_keeps_stack() {
+        let mut calculator = Calculator::new();
+        calculator.stack.push_back(StackValue::Number(BigInt::from(5)));
+        calculator.stack.push_back(StackValue::Number(BigInt::from(0)));
+        assert_eq!(calculator.div(), Err("division by zero".to_owned()));
+        assert_eq!(calculator.modulo(), Err("division by zero".to_owned()));
+        assert_eq!(calculator.div_rem(), Err("division by zero".to_owned()));
+        assert_eq!(calculator.stack.len(), 2);
+        assert_eq!(*calculator.stack.front().unwrap(), StackValue::Number(BigInt::from(5)));
+        assert_eq!(*calculator.stack.back().unwrap(), StackValue::Number(BigInt::from(0)));
+    }
+
+    #[test]
+    fn test_non_numeric_keeps_stack() {
+        let mut calculator = Calculator::new();
+        calculator.stack.push_back(StackValue::String("a".to_owned()));
+        calculator.stack.push_back(StackValue::Number(BigInt::from(5)));
+        assert_eq!(calculator.add(), Err("non-numeric value".to_owned()));
+        assert_eq!(calculator.stack.len(), 2);
+        assert_eq!(*calculator.stack.front().unwrap(), StackValue::String("a".to_owned()));
+    }
+
+    #[test]
+    fn test_missing_arguments_keep_stack() {
+        let mut calculator = Calculator::new();
+        calculator.stack.push_back(StackValue::Number(BigInt::from(5)));
+        calculator.stack.push_back(StackValue::Number(BigInt::from(6)));
+        assert_eq!(calculator.mod_exp(), Err("stack empty!".to_owned()));
+        assert_eq!(calculator.stack.len(), 2);
+    }
+
+    #[test]
+    fn test_invalid_arguments_keep_stack() {
+        let mut calculator = Calculator::new();
+        calculator.stack.push_back(StackValue::Number(BigInt::from(2)));
+        calculator.stack.push_back(StackValue::Number(BigInt::from(-1)));
+        assert_eq!(calculator.exp(), Err("negative exponent".to_owned()));
+        assert_eq!(calculator.sqrt(), Err("square root of negative number".to_owned()));
+        assert_eq!(calculator.pop_precision(), Err("precision out of range: -1".to_owned()));
+        assert_eq!(calculator.stack.len(), 2);
+        assert_eq!(calculator.precision, 0);
+    }
+
+    #[test]
+    fn test_mod_exp_zero_modulus_keeps_stack() {
+        let mut calculator = Calculator::new();
+        calculator.stack.push_back(StackValue::Number(BigInt::from(4)));
+        calculator.stack.push_back(StackValue::Number(BigInt::from(13)));
+        calculator.stack.push_back(StackValue::Number(BigInt::from(0)));
+        assert_eq!(calculator.mod_exp(), Err("modulus must be non-zero".to_owned()));
+        assert_eq!(calculator.stack.len(), 3);
+    }
+
+    #[test]
+    fn test_execute_if_empty_register_keeps_stack() {
+        let mut calculator = Calculator::new();
+        calculator.stack.push_back(StackValue::Number(BigInt::from(1)));
+        calculator.stack.push_back(StackValue::Number(BigInt::from(2)));
+        assert_eq!(calculator.execute_if('a', |_| true), Err("register a is empty".to_owned()));
+        assert_eq!(calculator.stack.len(), 2);
+        assert_eq!(calculator.execute_if('a', |_| false), Ok(OpResult::Ok));
+        assert_eq!(calculator.stack.len(), 0);
+    }
+
     #[test]
     fn test_bignum() {
         let mut calculator = Calculator::new();
@@ -530,4 +1624,160 @@ mod test {
         calculator.exp().unwrap();
         assert_eq!(&StackValue::Number(expected), calculator.peek().unwrap());
     }
+
+    #[test]
+    fn test_typed_accessors() {
+        let mut calculator = Calculator::new();
+        calculator.set_register('a', StackValue::Number(BigInt::from(1)));
+        calculator.set_register('a', StackValue::String("x".to_owned()));
+        calculator.set_array_value('a', 3, StackValue::Number(BigInt::from(5)));
+        assert_eq!(calculator.register('a').and_then(StackValue::as_string), Some("x"));
+        assert_eq!(calculator.register_stack('a').len(), 1);
+        assert_eq!(calculator.array_value('a', 3).and_then(StackValue::as_number), Some(&BigInt::from(5)));
+        assert_eq!(calculator.array_value('a', 4), None);
+
+        assert_eq!(calculator.set_input_radix(16), Ok(()));
+        assert_eq!(calculator.set_output_radix(1), Err("output radix out of range: 1".to_owned()));
+        calculator.set_precision(3);
+        assert_eq!((calculator.input_radix(), calculator.output_radix(), calculator.precision()), (16, 10, 3));
+    }
+
+    #[test]
+    fn test_execute_op() {
+        let mut calculator = Calculator::new();
+        calculator.capture_output();
+        calculator.execute(&Op::Push(BigInt::from(3))).unwrap();
+        calculator.execute(&Op::PushString("d*p".to_owned())).unwrap();
+        assert_eq!(calculator.execute(&Op::Execute), Ok(OpResult::Ok));
+        assert_eq!(calculator.output(), ["9\n"]);
+        assert_eq!(calculator.execute(&Op::Store('a')), Ok(OpResult::Ok));
+        assert_eq!(calculator.execute(&Op::Store('a')), Err("stack empty!".to_owned()));
+        assert_eq!(calculator.execute(&Op::Exit), Ok(OpResult::Exit));
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut calculator = Calculator::new();
+        calculator.set_dialect(Dialect::Gnu);
+        calculator.push(StackValue::Number(BigInt::from(16))).unwrap();
+        calculator.pop_output_radix().unwrap();
+        calculator.push(StackValue::Number(BigInt::from(1))).unwrap();
+        calculator.store('a').unwrap();
+        calculator.set_array_value('b', 0, StackValue::Number(BigInt::from(2)));
+        calculator.push(StackValue::Number(BigInt::from(3))).unwrap();
+
+        calculator.reset();
+        assert_eq!(calculator.stack.len(), 0);
+        assert_eq!(calculator.register_names(), vec![]);
+        assert_eq!(calculator.output_radix(), 10);
+        assert_eq!(calculator.dialect(), Dialect::Gnu);
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let mut calculator = Calculator::new();
+        assert!(!calculator.undo());
+
+        calculator.checkpoint();
+        calculator.push(StackValue::Number(BigInt::from(1))).unwrap();
+        calculator.store('a').unwrap();
+        calculator.checkpoint();
+        calculator.push(StackValue::Number(BigInt::from(2))).unwrap();
+        calculator.checkpoint();
+        calculator.checkpoint();
+        calculator.clear().unwrap();
+
+        assert!(calculator.undo());
+        assert_eq!(calculator.stack.len(), 1);
+        assert!(calculator.undo());
+        assert_eq!(calculator.stack.len(), 0);
+        assert_eq!(calculator.register_names(), vec!['a']);
+        assert!(calculator.undo());
+        assert_eq!(calculator.register_names(), vec![]);
+        assert!(!calculator.undo());
+
+        assert!(calculator.redo());
+        assert!(calculator.redo());
+        assert_eq!(calculator.stack.len(), 1);
+        calculator.checkpoint();
+        assert!(!calculator.redo());
+    }
+
+    #[test]
+    fn test_undo_unchanged() {
+        let mut calculator = Calculator::new();
+        for i in 0..1000 {
+            calculator.push(StackValue::Number(BigInt::from(i))).unwrap();
+        }
+        calculator.checkpoint();
+        calculator.peek();
+        assert!(!calculator.undo());
+
+        calculator.checkpoint();
+        calculator.pop();
+        assert!(calculator.undo());
+        assert_eq!(calculator.stack.len(), 1000);
+
+        let mut calculator = Calculator::new();
+        calculator.checkpoint();
+        assert!(!calculator.undo());
+    }
+
+    #[test]
+    fn test_undo_limit() {
+        let mut calculator = Calculator::new();
+        for i in 0..UNDO_LIMIT + 10 {
+            calculator.checkpoint();
+            calculator.push(StackValue::Number(BigInt::from(i))).unwrap();
+        }
+        while calculator.undo() {}
+        assert_eq!(calculator.stack.len(), 10);
+    }
+
+    #[test]
+    fn test_session_round_trip() {
+        let mut calculator = Calculator::new();
+        calculator.push(StackValue::Number(BigInt::from(-42))).unwrap();
+        calculator.push(StackValue::String("a\\b\nc ".to_owned())).unwrap();
+        calculator.push(StackValue::String("[d*]".to_owned())).unwrap();
+        calculator.store(' ').unwrap();
+        calculator.push(StackValue::Number(BigInt::from(16))).unwrap();
+        calculator.push_register('\n').unwrap();
+        calculator.set_array_value('a', 3, StackValue::Number(BigInt::from(5)));
+        calculator.push(StackValue::Number(BigInt::from(3))).unwrap();
+        calculator.pop_precision().unwrap();
+
+        let mut session = Vec::new();
+        calculator.save_to(&mut session).unwrap();
+        let text = String::from_utf8(session.clone()).unwrap();
+        assert!(text.starts_with("dc session 1\ninput_radix 10\noutput_radix 10\nprecision 3\n"));
+        assert!(text.contains("stack number 0 -42\nstack string a\\\\b\\nc \n"));
+        assert!(text.contains("register 10 number 0 16\nregister 32 string [d*]\narray 97 3 number 0 5\n"));
+
+        let mut restored = Calculator::new();
+        restored.load_from(session.as_slice()).unwrap();
+        assert!(restored.snapshot() == calculator.snapshot());
+    }
+
+    #[test]
+    fn test_session_invalid() {
+        let mut calculator = Calculator::new();
+        calculator.push(StackValue::Number(BigInt::from(1))).unwrap();
+
+        let load = |calculator: &mut Calculator, session: &str| {
+            calculator.load_from(session.as_bytes()).map_err(|err| err.to_string())
+        };
+        assert_eq!(load(&mut calculator, "1 2 3"), Err("line 1: not a dc session".to_owned()));
+        assert_eq!(load(&mut calculator, "dc session 2\n"), Err("line 1: unsupported session version: 2".to_owned()));
+        assert_eq!(
+            load(&mut calculator, "dc session 1\nstack number 0 5\nstack number 2 15\n"),
+            Err("line 3: unsupported number scale: 2".to_owned())
+        );
+        assert_eq!(load(&mut calculator, "dc session 1\ninput_radix 1\n"), Err("line 2: invalid setting: 1".to_owned()));
+        assert_eq!(load(&mut calculator, "dc session 1\nfoo 1\n"), Err("line 2: unknown entry: foo".to_owned()));
+        assert_eq!(calculator.stack.len(), 1);
+
+        assert_eq!(load(&mut calculator, "dc session 1\n"), Ok(()));
+        assert_eq!(calculator.stack.len(), 0);
+    }
 }
@@ -0,0 +1,219 @@
+use crate::calculator::{Calculator, StackValue};
+use num_bigint::BigInt;
+
+/// Parses RFC 4180 CSV data into records of fields. Both CRLF and LF line endings are accepted.
+pub fn parse(input: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut chars = input.chars().peekable();
+    let mut line = 1;
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '"' if field.is_empty() => {
+                let start = line;
+                loop {
+                    match chars.next() {
+                        Some('"') if chars.peek() == Some(&'"') => {
+                            chars.next();
+                            field.push('"');
+                        }
+                        Some('"') => break,
+                        Some(ch) => {
+                            if ch == '\n' {
+                                line += 1;
+                            }
+                            field.push(ch);
+                        }
+                        None => return Err(format!("unterminated quoted field on line {}", start)),
+                    }
+                }
+            }
+            ',' => record.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => (),
+            '\n' => {
+                line += 1;
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            _ => field.push(ch),
+        }
+    }
+
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
+/// Formats the fields as one CSV record, terminated by CRLF. Fields are quoted only when needed.
+pub fn format_record(fields: &[String]) -> String {
+    let fields = fields
+        .iter()
+        .map(|field| {
+            if field.contains([',', '"', '\r', '\n']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.clone()
+            }
+        })
+        .collect::<Vec<String>>();
+
+    format!("{}\r\n", fields.join(","))
+}
+
+/// Gives the fields of a row to the program, replacing everything left from the previous row:
+/// in the array of register `c` with their count on its stack, or pushed onto the stack. Numbers
+/// are read at the calculator's scale, see `Calculator::set_scale`. A field with more fraction
+/// digits than that cannot be read exactly, and is an error.
+pub fn load_record(calculator: &mut Calculator, record: &[String], on_stack: bool) -> Result<(), String> {
+    let scale = calculator.scale() as usize;
+    let mut values = Vec::with_capacity(record.len());
+    for field in record {
+        let number = parse_decimal(field, scale)?;
+        values.push(number.map_or_else(|| StackValue::String(field.clone()), StackValue::Number));
+    }
+
+    calculator.clear().unwrap();
+    if on_stack {
+        for value in values {
+            calculator.push(value).unwrap();
+        }
+    } else {
+        calculator.clear_array('c');
+        calculator.set_register('c', calculator.number(BigInt::from(values.len())));
+        for (index, value) in values.into_iter().enumerate() {
+            calculator.set_array_value('c', index, value);
+        }
+    }
+    Ok(())
+}
+
+/// Returns the values left on the stack as the fields of an output row, numbers as decimals at
+/// the calculator's scale.
+pub fn output_record(calculator: &Calculator) -> Vec<String> {
+    calculator.iter().map(|value| calculator.format(value)).collect()
+}
+
+/// Returns the largest number of fraction digits of the decimal fields, the scale that reads all
+/// of them exactly.
+pub fn scale(records: &[Vec<String>]) -> u32 {
+    records.iter().flatten().filter_map(|field| fraction_digits(field)).max().unwrap_or(0) as u32
+}
+
+/// Returns the number of fraction digits of a decimal number such as `-1.50`, or `None` if the
+/// field is not one. Both `-` and `_` are accepted as the sign.
+fn fraction_digits(field: &str) -> Option<usize> {
+    let unsigned = field.strip_prefix(['-', '_']).unwrap_or(field);
+    let (whole, fraction) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+    let digits = |part: &str| part.chars().all(|ch| ch.is_ascii_digit());
+    if whole.len() + fraction.len() == 0 || !digits(whole) || !digits(fraction) {
+        return None;
+    }
+    Some(fraction.len())
+}
+
+/// Reads a decimal number as a whole number of units of 10^-scale.
+fn parse_decimal(field: &str, scale: usize) -> Result<Option<BigInt>, String> {
+    let digits = match fraction_digits(field) {
+        Some(digits) if digits > scale => return Err(format!("{} has more than {} decimals", field, scale)),
+        Some(digits) => digits,
+        None => return Ok(None),
+    };
+
+    let unsigned = field.strip_prefix(['-', '_']);
+    let text = format!("{}{}", unsigned.unwrap_or(field).replace('.', ""), "0".repeat(scale - digits));
+    let num = text.parse::<BigInt>().unwrap_or_default();
+    Ok(Some(if unsigned.is_some() { -num } else { num }))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::csv::*;
+    use crate::calculator::Calculator;
+    use crate::process_input;
+
+    fn record(fields: &[&str]) -> Vec<String> {
+        fields.iter().map(|field| field.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_simple() {
+        assert_eq!(parse("a,b\r\n1,2\r\n"), Ok(vec![record(&["a", "b"]), record(&["1", "2"])]));
+        assert_eq!(parse("a,b\n1,2"), Ok(vec![record(&["a", "b"]), record(&["1", "2"])]));
+    }
+
+    #[test]
+    fn parse_empty_fields() {
+        assert_eq!(parse(",1,\n\n"), Ok(vec![record(&["", "1", ""]), record(&[""])]));
+        assert_eq!(parse(""), Ok(vec![]));
+    }
+
+    #[test]
+    fn parse_quoted() {
+        assert_eq!(
+            parse("\"a,b\",\"say \"\"hi\"\"\",\"two\nlines\"\n"),
+            Ok(vec![record(&["a,b", "say \"hi\"", "two\nlines"])])
+        );
+    }
+
+    #[test]
+    fn parse_unterminated() {
+        assert_eq!(parse("1\n\"a,b\n"), Err("unterminated quoted field on line 2".to_owned()));
+    }
+
+    #[test]
+    fn ragged_rows() {
+        let mut calculator = Calculator::new();
+        load_record(&mut calculator, &record(&["1", "2", "3"]), false).unwrap();
+        load_record(&mut calculator, &record(&["4"]), false).unwrap();
+        process_input(&mut calculator, "lc 0;c 1;c").unwrap();
+        assert_eq!(output_record(&calculator), record(&["1", "4", "0"]));
+        assert_eq!(calculator.register_array('c').len(), 1);
+    }
+
+    #[test]
+    fn decimal_fields() {
+        let records = vec![record(&["Widget", "3", "12.5"]), record(&["Gadget", "_2", "0.05"])];
+        assert_eq!(scale(&records), 2);
+
+        let mut calculator = Calculator::new();
+        calculator.set_scale(2);
+        load_record(&mut calculator, &records[0], false).unwrap();
+        process_input(&mut calculator, "0;c 1;c 2;c * lc").unwrap();
+        assert_eq!(output_record(&calculator), record(&["Widget", "37.50", "3.00"]));
+
+        load_record(&mut calculator, &records[1], true).unwrap();
+        process_input(&mut calculator, "* d 3/ r").unwrap();
+        assert_eq!(output_record(&calculator), record(&["Gadget", "-0.03", "-0.10"]));
+
+        load_record(&mut calculator, &record(&["1.50", ".25", "-7"]), true).unwrap();
+        process_input(&mut calculator, "++").unwrap();
+        assert_eq!(output_record(&calculator), record(&["-5.25"]));
+        assert_eq!(
+            load_record(&mut calculator, &record(&["1.505"]), true),
+            Err("1.505 has more than 2 decimals".to_owned())
+        );
+        assert_eq!(output_record(&calculator), record(&["-5.25"]));
+    }
+
+    #[test]
+    fn mixed_decimals() {
+        let records = vec![record(&["3", "12.50", "0.125"])];
+        let mut calculator = Calculator::new();
+        calculator.set_scale(scale(&records));
+        load_record(&mut calculator, &records[0], true).unwrap();
+        process_input(&mut calculator, "8* sa * la").unwrap();
+        assert_eq!(output_record(&calculator), record(&["37.500", "1.000"]));
+    }
+
+    #[test]
+    fn format() {
+        assert_eq!(format_record(&record(&["1", "a b"])), "1,a b\r\n");
+        assert_eq!(format_record(&record(&["a,b", "say \"hi\""])), "\"a,b\",\"say \"\"hi\"\"\"\r\n");
+        assert_eq!(format_record(&[]), "\r\n");
+    }
+}
@@ -0,0 +1,39 @@
+use crate::calculator::Calculator;
+use crate::token::{Op, Position};
+
+/// The operation the interpreter is about to run, and where it is.
+#[derive(Debug)]
+pub struct Step<'a> {
+    pub op: &'a Op,
+    /// The position of the operation in its frame: the input, or the macro being run.
+    pub position: Position,
+    /// The name of the script being run, if it has one.
+    pub source: Option<&'a str>,
+    /// The number of macros being run, 0 for the input itself.
+    pub depth: usize,
+    /// Identifies the running frame. A macro run by a tail call gets a new one at the same depth.
+    pub frame: usize,
+    /// The register the running macro came from, if known.
+    pub register: Option<char>,
+    /// Whether this is the first operation of its frame.
+    pub entering: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Control {
+    Run,
+    /// Stops the execution as if `q` was run.
+    Quit,
+}
+
+/// Hooks into the interpreter, set with `Calculator::set_debugger`. It is called before every
+/// operation, and can inspect and change the calculator or stop the execution.
+pub trait Debugger {
+    fn before(&mut self, calculator: &mut Calculator, step: &Step<'_>) -> Control;
+
+    /// Called when the input has run to its end, after its last operation. `source` is the name
+    /// of the script, if it has one.
+    fn finished(&mut self, _calculator: &mut Calculator, _source: Option<&str>) -> Control {
+        Control::Run
+    }
+}
@@ -0,0 +1,318 @@
+use dc::calculator::{Calculator, StackValue};
+use dc::debug::{Control, Debugger, Step};
+use dc::input::{Input, Reader, Stdin};
+use dc::meta;
+use std::fs::File;
+use std::io::{self, BufReader, IsTerminal};
+
+const HELP: &str = "\
+step, s             run the next operation, entering macros
+next, n             run the next operation, stepping over the macros it runs
+finish, f           run until the current macro returns
+continue, c         run until a breakpoint or a watchpoint stops
+break, b [N|r]      stop at line N of the current script (file:N of another one), or when the
+                    macro of register r is entered; without argument, list the breakpoints and
+                    watchpoints
+delete, d N|r       remove a breakpoint or a watchpoint
+watch, w r          stop after an operation changes register r
+stack               show the stack
+regs                show the registers
+quit, q             stop running
+An empty line repeats the last step, next, finish or continue.
+";
+
+#[derive(Debug, Clone, Copy)]
+enum Mode {
+    Step,
+    /// Stops in the frame where `next` was given, or after it returned.
+    Next { depth: usize, frame: usize },
+    Finish { depth: usize },
+    Continue,
+}
+
+/// A register being watched, with its contents when the debugger last looked at it.
+struct Watch {
+    register: char,
+    stack: Vec<StackValue>,
+    array: Vec<(usize, StackValue)>,
+}
+
+/// The `--debug` session: stops before operations and reads commands from the terminal, writing
+/// to the standard error.
+pub struct Session {
+    /// Where the commands come from, see `commands`.
+    commands: Box<dyn Input>,
+    mode: Mode,
+    /// Line breakpoints, by script name (`None` for `-e` and standard input) and line.
+    lines: Vec<(Option<String>, usize)>,
+    registers: Vec<char>,
+    watches: Vec<Watch>,
+    /// The line of the last operation run at depth 0, a line breakpoint stops only on entering it.
+    line: Option<usize>,
+    last_command: String,
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session {
+            commands: commands(),
+            mode: Mode::Step,
+            lines: Vec::new(),
+            registers: Vec::new(),
+            watches: Vec::new(),
+            line: None,
+            last_command: "step".to_owned(),
+        }
+    }
+
+    /// Tells why the debugger stops before this step, if it does.
+    fn stop_reason(&mut self, calculator: &Calculator, step: &Step<'_>) -> Option<String> {
+        let mut reasons = self.changed_watches(calculator);
+        if step.depth == 0 {
+            let line = step.position.line;
+            let set = self.lines.iter().any(|(source, other)| *other == line && source.as_deref() == step.source);
+            if self.line != Some(line) && set {
+                reasons.push(format!("breakpoint at line {}", line));
+            }
+            self.line = Some(line);
+        }
+        if let Some(register) = step.register.filter(|register| step.entering && self.registers.contains(register)) {
+            reasons.push(format!("breakpoint at macro {}", register));
+        }
+
+        let stepped = match self.mode {
+            Mode::Step => true,
+            Mode::Next { depth, frame } => step.depth < depth || (step.depth == depth && step.frame == frame),
+            Mode::Finish { depth } => step.depth < depth,
+            Mode::Continue => false,
+        };
+
+        if !reasons.is_empty() {
+            Some(reasons.join(", "))
+        } else if stepped {
+            Some(String::new())
+        } else {
+            None
+        }
+    }
+
+    /// Describes the watched registers that changed since the last look.
+    fn changed_watches(&mut self, calculator: &Calculator) -> Vec<String> {
+        let mut changes = Vec::new();
+        for watch in &mut self.watches {
+            let (stack, array) = contents(calculator, watch.register);
+            if stack != watch.stack || array != watch.array {
+                changes.push(format!(
+                    "watch {}: {} -> {}",
+                    watch.register,
+                    describe(&watch.stack, &watch.array),
+                    describe(&stack, &array)
+                ));
+                watch.stack = stack;
+                watch.array = array;
+            }
+        }
+        changes
+    }
+
+    /// Runs the commands given at a stop until one resumes the execution. Without a step the stop
+    /// is at the end of a script.
+    fn prompt(&mut self, calculator: &Calculator, source: Option<&str>, step: Option<&Step<'_>>) -> Control {
+        let (depth, frame) = step.map_or((0, 0), |step| (step.depth, step.frame));
+        loop {
+            eprint!("(dc) ");
+            let line = match self.commands.read_line() {
+                Ok(Some(line)) => line,
+                Ok(None) | Err(_) => {
+                    eprintln!();
+                    return Control::Quit;
+                }
+            };
+
+            let line = line.trim();
+            let command = if line.is_empty() { self.last_command.clone() } else { line.to_owned() };
+
+            let mut words = command.split_whitespace();
+            let name = words.next().unwrap_or("");
+            let argument = words.next();
+            match (name, argument) {
+                ("step", None) | ("s", None) => self.mode = Mode::Step,
+                ("next", None) | ("n", None) => self.mode = Mode::Next { depth, frame },
+                ("finish", None) | ("f", None) if depth == 0 => {
+                    eprintln!("not in a macro");
+                    continue;
+                }
+                ("finish", None) | ("f", None) => self.mode = Mode::Finish { depth },
+                ("continue", None) | ("c", None) => self.mode = Mode::Continue,
+                ("break", None) | ("b", None) => {
+                    eprint!("{}", self.breakpoints());
+                    continue;
+                }
+                ("break", Some(target)) | ("b", Some(target)) => {
+                    match parse_target(target) {
+                        Ok(Target::Line(script, line)) => self.lines.push((script.or_else(|| source.map(str::to_owned)), line)),
+                        Ok(Target::Register(register)) => self.registers.push(register),
+                        Err(err) => eprintln!("{}", err),
+                    }
+                    continue;
+                }
+                ("delete", Some(target)) | ("d", Some(target)) => {
+                    match parse_target(target) {
+                        Ok(Target::Line(script, line)) => {
+                            let script = script.or_else(|| source.map(str::to_owned));
+                            self.lines.retain(|other| *other != (script.clone(), line));
+                        }
+                        Ok(Target::Register(register)) => {
+                            self.registers.retain(|&other| other != register);
+                            self.watches.retain(|watch| watch.register != register);
+                        }
+                        Err(err) => eprintln!("{}", err),
+                    }
+                    continue;
+                }
+                ("watch", Some(target)) | ("w", Some(target)) => {
+                    match parse_target(target) {
+                        Ok(Target::Register(register)) => {
+                            let (stack, array) = contents(calculator, register);
+                            self.watches.push(Watch { register, stack, array });
+                        }
+                        Ok(Target::Line(..)) | Err(_) => eprintln!("usage: watch r"),
+                    }
+                    continue;
+                }
+                ("stack", None) => {
+                    eprint!("{}", meta::stack(calculator));
+                    continue;
+                }
+                ("regs", None) => {
+                    eprint!("{}", meta::registers(calculator));
+                    continue;
+                }
+                ("help", None) | ("h", None) => {
+                    eprint!("{}", HELP);
+                    continue;
+                }
+                ("quit", None) | ("q", None) => return Control::Quit,
+                _ => {
+                    eprintln!("unknown command: {}, try help", command);
+                    continue;
+                }
+            }
+            self.last_command = command;
+            return Control::Run;
+        }
+    }
+
+    fn breakpoints(&self) -> String {
+        let mut listing = String::new();
+        for (source, line) in &self.lines {
+            listing.push_str(&format!("break {}:{}\n", source.as_deref().unwrap_or(INPUT), line));
+        }
+        for register in &self.registers {
+            listing.push_str(&format!("break macro {}\n", register));
+        }
+        for watch in &self.watches {
+            listing.push_str(&format!("watch {}\n", watch.register));
+        }
+        if listing.is_empty() {
+            "no breakpoints or watchpoints\n".to_owned()
+        } else {
+            listing
+        }
+    }
+}
+
+impl Debugger for Session {
+    fn before(&mut self, calculator: &mut Calculator, step: &Step<'_>) -> Control {
+        let reason = match self.stop_reason(calculator, step) {
+            Some(reason) => reason,
+            None => return Control::Run,
+        };
+
+        if !reason.is_empty() {
+            eprintln!("{}", reason);
+        }
+        eprintln!("{} depth={} op={}", location(step), step.depth, step.op);
+        self.prompt(calculator, step.source, Some(step))
+    }
+
+    fn finished(&mut self, calculator: &mut Calculator, source: Option<&str>) -> Control {
+        self.line = None;
+        let changes = self.changed_watches(calculator);
+        if changes.is_empty() {
+            return Control::Run;
+        }
+
+        eprintln!("{}", changes.join(", "));
+        eprintln!("{}: end", source.unwrap_or(INPUT));
+        self.prompt(calculator, source, None)
+    }
+}
+
+/// Reads the commands from the terminal when the standard input is redirected, so they do not
+/// mix with the lines read by `?`. Without a terminal both come from the standard input.
+fn commands() -> Box<dyn Input> {
+    if io::stdin().is_terminal() {
+        return Box::new(Stdin);
+    }
+    match File::open("/dev/tty") {
+        Ok(tty) => Box::new(Reader::new(BufReader::new(tty))),
+        Err(_) => Box::new(Stdin),
+    }
+}
+
+/// The name shown for input that does not come from a script file.
+const INPUT: &str = "<input>";
+
+enum Target {
+    /// A line, of the named script or of the current one.
+    Line(Option<String>, usize),
+    Register(char),
+}
+
+/// Reads a breakpoint target: a line number, optionally after a script name and a colon, or a
+/// register name.
+fn parse_target(target: &str) -> Result<Target, String> {
+    if let Ok(line) = target.parse() {
+        return Ok(Target::Line(None, line));
+    }
+    if let Some((script, line)) = target.rsplit_once(':').filter(|(script, _)| !script.is_empty()) {
+        let line = line.parse().map_err(|_| format!("not a line: {}", target))?;
+        return Ok(Target::Line(Some(script).filter(|script| *script != INPUT).map(str::to_owned), line));
+    }
+    let mut chars = target.chars();
+    match (chars.next(), chars.next()) {
+        (Some(register), None) => Ok(Target::Register(register)),
+        _ => Err(format!("not a line or a register: {}", target)),
+    }
+}
+
+/// Shows where the step is: in the script, or in a macro.
+fn location(step: &Step<'_>) -> String {
+    let position = format!("{}:{}", step.position.line, step.position.column);
+    match (step.depth, step.register) {
+        (0, _) => format!("{}:{}", step.source.unwrap_or(INPUT), position),
+        (_, Some(register)) => format!("macro {} {}", register, position),
+        (_, None) => format!("macro {}", position),
+    }
+}
+
+fn contents(calculator: &Calculator, register: char) -> (Vec<StackValue>, Vec<(usize, StackValue)>) {
+    let stack = calculator.register_stack(register).into_iter().cloned().collect();
+    let array = calculator
+        .register_array(register)
+        .into_iter()
+        .map(|(index, value)| (index, value.clone()))
+        .collect();
+    (stack, array)
+}
+
+fn describe(stack: &[StackValue], array: &[(usize, StackValue)]) -> String {
+    let mut values = stack.iter().map(StackValue::literal).collect::<Vec<String>>();
+    values.extend(array.iter().map(|(index, value)| format!("{}={}", index, value.literal())));
+    if values.is_empty() {
+        "empty".to_owned()
+    } else {
+        values.join(" ")
+    }
+}
@@ -0,0 +1,110 @@
+use dc::calculator::{Calculator, Dialect, StackValue};
+use dc::highlight::{awaits_register, highlight, Style};
+use rustyline::completion::{Completer, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+use std::borrow::Cow;
+
+/// Colors the line being typed and completes register names, with the library's lexer.
+#[derive(Default)]
+pub struct DcHelper {
+    dialect: Dialect,
+    /// The lines typed before the current one, while a string is still open.
+    pending: String,
+    /// The registers holding values, with a preview of their top value.
+    registers: Vec<(char, String)>,
+}
+
+impl DcHelper {
+    /// Takes the dialect and the registers of the calculator, and the unfinished input, before
+    /// reading a line.
+    pub fn update(&mut self, calculator: &Calculator, pending: &str) {
+        self.dialect = calculator.dialect();
+        self.pending = pending.to_owned();
+        self.registers = calculator
+            .register_names()
+            .into_iter()
+            .map(|name| {
+                let preview = match calculator.register_stack(name).last() {
+                    Some(value) => preview(value),
+                    None => format!("array of {}", calculator.register_array(name).len()),
+                };
+                (name, preview)
+            })
+            .collect();
+    }
+}
+
+impl Completer for DcHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let input = format!("{}{}", self.pending, &line[..pos]);
+        if !awaits_register(&input, self.dialect) {
+            return Ok((pos, Vec::new()));
+        }
+
+        let candidates = self
+            .registers
+            .iter()
+            .map(|(name, preview)| Pair { display: format!("{}  {}", name, preview), replacement: name.to_string() })
+            .collect();
+        Ok((pos, candidates))
+    }
+}
+
+impl Highlighter for DcHelper {
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        let input = format!("{}{}", self.pending, line);
+        let offset = self.pending.len();
+        let mut colored = String::with_capacity(line.len() * 2);
+        let mut end = 0;
+
+        for (span, style) in highlight(&input, self.dialect) {
+            if span.end <= offset {
+                continue;
+            }
+            let start = span.start.max(offset) - offset;
+            colored.push_str(&line[end..start]);
+            colored.push_str(&format!("\x1b[{}m{}\x1b[0m", color(style), &line[start..span.end - offset]));
+            end = span.end - offset;
+        }
+        colored.push_str(&line[end..]);
+
+        Cow::Owned(colored)
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize, _forced: bool) -> bool {
+        true
+    }
+}
+
+impl Hinter for DcHelper {
+    type Hint = String;
+}
+
+impl Validator for DcHelper {}
+
+impl Helper for DcHelper {}
+
+fn color(style: Style) -> &'static str {
+    match style {
+        Style::Number => "36",
+        Style::String => "32",
+        Style::Register => "33",
+        Style::Command => "1",
+        Style::Comment => "2",
+        Style::Error => "1;31",
+    }
+}
+
+fn preview(value: &StackValue) -> String {
+    let text = value.literal();
+    if text.chars().count() > 30 {
+        format!("{}...", text.chars().take(30).collect::<String>())
+    } else {
+        text
+    }
+}
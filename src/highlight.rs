@@ -0,0 +1,123 @@
+use crate::calculator::Dialect;
+use crate::token::{lex_partial, LexErrorKind, Op};
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Style {
+    Number,
+    String,
+    Register,
+    Command,
+    Comment,
+    /// Unknown or unsupported commands, and an unclosed string.
+    Error,
+}
+
+/// Splits the input into styled parts, using the same lexer as the interpreter, so highlighting
+/// never disagrees with execution. Whitespace is left out.
+pub fn highlight(str: &str, dialect: Dialect) -> Vec<(Range<usize>, Style)> {
+    let (tokens, error) = lex_partial(str, dialect);
+    let mut parts = Vec::new();
+    let mut end = 0;
+
+    for token in tokens {
+        comments(str, end..token.span.start, &mut parts);
+        end = token.span.end;
+
+        let span = token.span;
+        match token.op {
+            Op::Push(_) => parts.push((span, Style::Number)),
+            Op::PushString(_) => parts.push((span, Style::String)),
+            Op::Unimplemented(_) | Op::Unsupported(_) => parts.push((span, Style::Error)),
+            Op::Store(register)
+            | Op::Load(register)
+            | Op::PushRegister(register)
+            | Op::PopRegister(register)
+            | Op::StoreArray(register)
+            | Op::LoadArray(register)
+            | Op::Conditional(_, register) => {
+                let name = span.end - register.len_utf8();
+                parts.push((span.start..name, Style::Command));
+                parts.push((name..span.end, Style::Register));
+            }
+            _ => parts.push((span, Style::Command)),
+        }
+    }
+
+    match error {
+        Some(error) => {
+            comments(str, end..error.span.start, &mut parts);
+            let style = if error.kind == LexErrorKind::MissingRegister { Style::Command } else { Style::Error };
+            parts.push((error.span, style));
+        }
+        None => comments(str, end..str.len(), &mut parts),
+    }
+
+    parts
+}
+
+/// Tells whether the input ends with a register command still missing its register name, such
+/// as `2 l`.
+pub fn awaits_register(str: &str, dialect: Dialect) -> bool {
+    lex_partial(str, dialect).1.is_some_and(|error| error.kind == LexErrorKind::MissingRegister)
+}
+
+/// Adds the comments in a stretch of the input between two commands.
+fn comments(str: &str, gap: Range<usize>, parts: &mut Vec<(Range<usize>, Style)>) {
+    let mut start = gap.start;
+    while let Some(hash) = str[start..gap.end].find('#') {
+        let comment = start + hash;
+        let end = str[comment..gap.end].find('\n').map_or(gap.end, |newline| comment + newline);
+        parts.push((comment..end, Style::Comment));
+        start = end;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::calculator::Dialect;
+    use crate::highlight::*;
+
+    fn styled(str: &str) -> Vec<(&str, Style)> {
+        highlight(str, Dialect::Extended)
+            .into_iter()
+            .map(|(span, style)| (&str[span], style))
+            .collect()
+    }
+
+    #[test]
+    fn highlight_commands() {
+        assert_eq!(
+            styled("[d1-]dsFx _2 !<é # loop\n p"),
+            vec![
+                ("[d1-]", Style::String),
+                ("d", Style::Command),
+                ("s", Style::Command),
+                ("F", Style::Register),
+                ("x", Style::Command),
+                ("_2", Style::Number),
+                ("!<", Style::Command),
+                ("é", Style::Register),
+                ("# loop", Style::Comment),
+                ("p", Style::Command),
+            ]
+        );
+    }
+
+    #[test]
+    fn highlight_errors() {
+        assert_eq!(styled("1 y"), vec![("1", Style::Number), ("y", Style::Error)]);
+        assert_eq!(styled("1 [a [b]"), vec![("1", Style::Number), ("[a [b]", Style::Error)]);
+        assert_eq!(styled("1 l"), vec![("1", Style::Number), ("l", Style::Command)]);
+        assert_eq!(highlight("7 2~", Dialect::Posix)[2].1, Style::Error);
+    }
+
+    #[test]
+    fn register_positions() {
+        assert!(awaits_register("1 l", Dialect::Extended));
+        assert!(awaits_register("1 ;", Dialect::Extended));
+        assert!(!awaits_register("1 ll", Dialect::Extended));
+        assert!(!awaits_register("[l", Dialect::Extended));
+        assert!(!awaits_register("# l", Dialect::Extended));
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, BufRead};
+
+/// Where `?` reads its lines from, set with `Calculator::set_input`.
+pub trait Input {
+    /// Reads a line, without its line break. Returns `None` at the end of the input.
+    fn read_line(&mut self) -> io::Result<Option<String>>;
+}
+
+/// Reads the standard input.
+#[derive(Debug, Default)]
+pub struct Stdin;
+
+impl Input for Stdin {
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        let mut line = String::new();
+        match io::stdin().lock().read_line(&mut line)? {
+            0 => Ok(None),
+            _ => Ok(Some(line.trim_end_matches(['\n', '\r']).to_owned())),
+        }
+    }
+}
+
+/// Hands out lines given in advance, then reports the end of the input.
+#[derive(Debug, Default)]
+pub struct Queue {
+    lines: VecDeque<String>,
+}
+
+impl Queue {
+    pub fn new(lines: Vec<String>) -> Queue {
+        Queue { lines: lines.into() }
+    }
+
+    pub fn push(&mut self, line: String) {
+        self.lines.push_back(line);
+    }
+}
+
+impl Input for Queue {
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        Ok(self.lines.pop_front())
+    }
+}
+
+/// Reads any buffered reader, such as a file or a terminal other than stdin.
+#[derive(Debug)]
+pub struct Reader<R: BufRead> {
+    reader: R,
+}
+
+impl<R: BufRead> Reader<R> {
+    pub fn new(reader: R) -> Reader<R> {
+        Reader { reader }
+    }
+}
+
+impl<R: BufRead> Input for Reader<R> {
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        let mut line = String::new();
+        match self.reader.read_line(&mut line)? {
+            0 => Ok(None),
+            _ => Ok(Some(line.trim_end_matches(['\n', '\r']).to_owned())),
+        }
+    }
+}
+
+/// Asks a function for every line, for input that is only known while the calculator runs.
+///
+/// ```
+/// use dc::input::Callback;
+/// use dc::prelude::*;
+///
+/// let mut calculator = Calculator::new();
+/// let mut answers = vec!["6 7*".to_owned()];
+/// calculator.set_input(Box::new(Callback::new(move || answers.pop())));
+/// calculator.eval("?").unwrap();
+/// assert_eq!(calculator.peek().and_then(StackValue::as_number), Some(&42.into()));
+/// ```
+pub struct Callback<F: FnMut() -> Option<String>> {
+    callback: F,
+}
+
+impl<F: FnMut() -> Option<String>> Callback<F> {
+    pub fn new(callback: F) -> Callback<F> {
+        Callback { callback }
+    }
+}
+
+impl<F: FnMut() -> Option<String>> Input for Callback<F> {
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        Ok((self.callback)())
+    }
+}
+
+impl<F: FnMut() -> Option<String>> fmt::Debug for Callback<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Callback")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::calculator::{Calculator, OpResult};
+    use crate::input::*;
+    use crate::process_input;
+
+    /// Fails like a terminal that went away.
+    struct Hangup;
+
+    impl Input for Hangup {
+        fn read_line(&mut self) -> io::Result<Option<String>> {
+            Err(io::Error::from(io::ErrorKind::BrokenPipe))
+        }
+    }
+
+    #[test]
+    fn queue() {
+        let mut queue = Queue::new(vec!["1".to_owned()]);
+        queue.push("2".to_owned());
+        let mut calculator = Calculator::new();
+        calculator.set_input(Box::new(queue));
+        process_input(&mut calculator, "? ? ? +").unwrap();
+        assert_eq!(calculator.iter().map(|value| value.to_string()).collect::<Vec<String>>(), vec!["3"]);
+    }
+
+    #[test]
+    fn empty_queue() {
+        let mut calculator = Calculator::new();
+        calculator.set_input(Box::new(Queue::default()));
+        assert_eq!(calculator.read_line(), Ok(None));
+        assert_eq!(process_input(&mut calculator, "?z"), Ok(OpResult::Ok));
+        assert_eq!(calculator.iter().count(), 1);
+    }
+
+    #[test]
+    fn reader() {
+        let mut calculator = Calculator::new();
+        calculator.set_input(Box::new(Reader::new("5\r\n[6]\n".as_bytes())));
+        process_input(&mut calculator, "? ? ?").unwrap();
+        assert_eq!(calculator.iter().map(|value| value.to_string()).collect::<Vec<String>>(), vec!["5", "6"]);
+    }
+
+    #[test]
+    fn callback_reads_nested() {
+        let mut lines = vec!["?".to_owned(), "[?]x 4".to_owned()];
+        let mut calculator = Calculator::new();
+        calculator.set_input(Box::new(Callback::new(move || lines.pop())));
+        process_input(&mut calculator, "?").unwrap();
+        assert_eq!(calculator.iter().map(|value| value.to_string()).collect::<Vec<String>>(), vec!["4"]);
+    }
+
+    #[test]
+    fn read_errors() {
+        let mut calculator = Calculator::new();
+        calculator.set_input(Box::new(Hangup));
+        assert!(calculator.read_line().unwrap_err().starts_with("could not read input: "));
+    }
+}
@@ -0,0 +1,167 @@
+use std::fmt;
+
+/// The first line of a journal, followed by the version of the format.
+const HEADER: &str = "dc journal";
+const VERSION: u32 = 1;
+
+/// Where a chunk of input came from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    Repl,
+    Expression,
+    File(String),
+    /// The script arguments, written as dc source.
+    Arguments,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Entry {
+    /// A chunk of input run as a whole.
+    Input(Source, String),
+    /// A line read by `?`.
+    Read(String),
+    /// Text written by a printing command.
+    Output(String),
+    /// A REPL colon command changing the state, such as `:undo`.
+    Command(String),
+}
+
+/// The input and output of a session, in the order they happened.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Journal {
+    pub entries: Vec<Entry>,
+}
+
+impl Journal {
+    /// Reads a journal written with `to_string`.
+    pub fn parse(text: &str) -> Result<Journal, String> {
+        let mut lines = text.lines().enumerate().map(|(index, line)| (index + 1, line));
+        match lines.next().and_then(|(_, header)| header.strip_prefix(HEADER)) {
+            Some(version) if version.trim() == VERSION.to_string() => (),
+            Some(version) => return Err(format!("line 1: unsupported journal version: {}", version.trim())),
+            None => return Err("line 1: not a dc journal".to_owned()),
+        }
+
+        let mut entries = Vec::new();
+        for (number, line) in lines.filter(|(_, line)| !line.is_empty()) {
+            let (kind, rest) = line.split_once(' ').unwrap_or((line, ""));
+            let entry = match kind {
+                "input" => {
+                    let (source, text) = rest.split_once(' ').unwrap_or((rest, ""));
+                    let source = match source {
+                        "repl" => Ok(Source::Repl),
+                        "expression" => Ok(Source::Expression),
+                        "arguments" => Ok(Source::Arguments),
+                        _ => match source.strip_prefix("file:") {
+                            Some(name) => unescape(name).map(Source::File),
+                            None => Err(format!("unknown source: {}", source)),
+                        },
+                    };
+                    source.and_then(|source| Ok(Entry::Input(source, unescape(text)?)))
+                }
+                "read" => unescape(rest).map(Entry::Read),
+                "output" => unescape(rest).map(Entry::Output),
+                "command" => unescape(rest).map(Entry::Command),
+                _ => Err(format!("unknown entry: {}", kind)),
+            };
+            entries.push(entry.map_err(|err| format!("line {}: {}", number, err))?);
+        }
+
+        Ok(Journal { entries })
+    }
+
+    /// Returns the text printed while running the entries, all of it concatenated.
+    pub fn output(entries: &[Entry]) -> String {
+        entries
+            .iter()
+            .filter_map(|entry| match entry {
+                Entry::Output(text) => Some(text.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Writes the journal as text: a `dc journal 1` header, then one entry per line, such as
+/// `input repl 1 2+p`, `input file:lib.dc [d*]sq`, `read 5`, `output 3\n` or `command :undo`.
+/// Backslashes and line breaks are escaped, and spaces in file names too.
+impl fmt::Display for Journal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} {}", HEADER, VERSION)?;
+        for entry in &self.entries {
+            match entry {
+                Entry::Input(Source::Repl, text) => writeln!(f, "input repl {}", escape(text)),
+                Entry::Input(Source::Expression, text) => writeln!(f, "input expression {}", escape(text)),
+                Entry::Input(Source::Arguments, text) => writeln!(f, "input arguments {}", escape(text)),
+                Entry::Input(Source::File(name), text) => {
+                    writeln!(f, "input file:{} {}", escape(name).replace(' ', "\\s"), escape(text))
+                }
+                Entry::Read(line) => writeln!(f, "read {}", escape(line)),
+                Entry::Output(text) => writeln!(f, "output {}", escape(text)),
+                Entry::Command(command) => writeln!(f, "command {}", escape(command)),
+            }?;
+        }
+        Ok(())
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+fn unescape(text: &str) -> Result<String, String> {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            unescaped.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => unescaped.push('\\'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some('s') => unescaped.push(' '),
+            other => return Err(format!("invalid escape: \\{}", other.map_or(String::new(), String::from))),
+        }
+    }
+    Ok(unescaped)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::journal::*;
+
+    #[test]
+    fn round_trip() {
+        let journal = Journal {
+            entries: vec![
+                Entry::Input(Source::File("my lib.dc".to_owned()), "[d*]sq\n# square\n".to_owned()),
+                Entry::Input(Source::Arguments, "_5 [a b]".to_owned()),
+                Entry::Input(Source::Repl, "? lqx p".to_owned()),
+                Entry::Read("7 \\ 8".to_owned()),
+                Entry::Output("49\n".to_owned()),
+                Entry::Command(":undo".to_owned()),
+                Entry::Input(Source::Expression, "".to_owned()),
+            ],
+        };
+        let text = journal.to_string();
+        assert!(text.starts_with("dc journal 1\ninput file:my\\slib.dc [d*]sq\\n# square\\n\n"));
+        assert!(text.contains("read 7 \\\\ 8\noutput 49\\n\n"));
+        assert_eq!(Journal::parse(&text), Ok(journal));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(Journal::parse("dc session 1\n"), Err("line 1: not a dc journal".to_owned()));
+        assert_eq!(Journal::parse("dc journal 9\n"), Err("line 1: unsupported journal version: 9".to_owned()));
+        assert_eq!(Journal::parse("dc journal 1\ninput tty 1p\n"), Err("line 2: unknown source: tty".to_owned()));
+        assert_eq!(Journal::parse("dc journal 1\nread \\x\n"), Err("line 2: invalid escape: \\x".to_owned()));
+    }
+
+    #[test]
+    fn output() {
+        let entries = vec![Entry::Output("1".to_owned()), Entry::Read("2".to_owned()), Entry::Output("3\n".to_owned())];
+        assert_eq!(Journal::output(&entries), "13\n");
+    }
+}
@@ -0,0 +1,118 @@
+use crate::calculator::{Calculator, Diagnostic, StackValue};
+
+/// Renders the state of the calculator as a JSON document: the stack (bottom first), the
+/// registers in use, the settings, and the captured output and errors.
+pub fn to_json(calculator: &Calculator) -> String {
+    let element = |stack_value| value(calculator, stack_value);
+    let stack = array(calculator.iter().map(element));
+    let registers = object(calculator.register_names().into_iter().map(|name| {
+        let register = object(vec![
+            ("stack".to_owned(), array(calculator.register_stack(name).into_iter().map(element))),
+            (
+                "array".to_owned(),
+                object(calculator.register_array(name).into_iter().map(|(index, stack_value)| (index.to_string(), element(stack_value)))),
+            ),
+        ]);
+        (name.to_string(), register)
+    }));
+    let output = array(calculator.output().iter().map(|text| string(text)));
+    let errors = array(calculator.errors().iter().map(diagnostic));
+
+    object(vec![
+        ("stack".to_owned(), stack),
+        ("registers".to_owned(), registers),
+        ("input_radix".to_owned(), calculator.input_radix().to_string()),
+        ("output_radix".to_owned(), calculator.output_radix().to_string()),
+        ("precision".to_owned(), calculator.precision().to_string()),
+        ("output".to_owned(), output),
+        ("errors".to_owned(), errors),
+    ])
+}
+
+fn value(calculator: &Calculator, value: &StackValue) -> String {
+    let mut fields = vec![("type".to_owned(), string(value.type_name()))];
+    if let Some(digits) = value.digits() {
+        fields.push(("digits".to_owned(), digits.to_string()));
+        fields.push(("scale".to_owned(), calculator.scale().to_string()));
+    }
+    fields.push(("value".to_owned(), string(&calculator.format(value))));
+    object(fields)
+}
+
+fn diagnostic(diagnostic: &Diagnostic) -> String {
+    object(vec![
+        ("message".to_owned(), string(&diagnostic.message)),
+        ("source".to_owned(), diagnostic.source.as_ref().map_or("null".to_owned(), |source| string(source))),
+        ("line".to_owned(), diagnostic.line.to_string()),
+        ("column".to_owned(), diagnostic.column.to_string()),
+    ])
+}
+
+fn object<I: IntoIterator<Item = (String, String)>>(fields: I) -> String {
+    let fields = fields
+        .into_iter()
+        .map(|(key, value)| format!("{}:{}", string(&key), value))
+        .collect::<Vec<String>>();
+    format!("{{{}}}", fields.join(","))
+}
+
+fn array<I: Iterator<Item = String>>(values: I) -> String {
+    format!("[{}]", values.collect::<Vec<String>>().join(","))
+}
+
+fn string(str: &str) -> String {
+    let mut escaped = String::with_capacity(str.len() + 2);
+    escaped.push('"');
+    for ch in str.chars() {
+        match ch {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            ch if (ch as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => escaped.push(ch),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use crate::calculator::Calculator;
+    use crate::json::*;
+    use crate::process_input;
+
+    #[test]
+    fn json_empty() {
+        let calculator = Calculator::new();
+        assert_eq!(
+            to_json(&calculator),
+            r#"{"stack":[],"registers":{},"input_radix":10,"output_radix":10,"precision":0,"output":[],"errors":[]}"#
+        );
+    }
+
+    #[test]
+    fn json_state() {
+        let mut calculator = Calculator::new();
+        calculator.capture_output();
+        process_input(&mut calculator, "_42 [a \"b\"] 7sr 1 0:r 5p y").unwrap();
+        assert_eq!(
+            to_json(&calculator),
+            concat!(
+                r#"{"stack":[{"type":"number","digits":2,"scale":0,"value":"-42"},{"type":"string","value":"a \"b\""},"#,
+                r#"{"type":"number","digits":1,"scale":0,"value":"5"}],"#,
+                r#""registers":{"r":{"stack":[{"type":"number","digits":1,"scale":0,"value":"7"}],"#,
+                r#""array":{"0":{"type":"number","digits":1,"scale":0,"value":"1"}}}},"#,
+                r#""input_radix":10,"output_radix":10,"precision":0,"output":["5\n"],"#,
+                r#""errors":[{"message":"'y' (0171) unimplemented","source":null,"line":1,"column":26}]}"#
+            )
+        );
+    }
+
+    #[test]
+    fn json_string_escapes() {
+        assert_eq!(string("a\"\\\n\u{1}"), r#""a\"\\\n\u0001""#);
+    }
+}
@@ -1,75 +1,378 @@
+//! An implementation of the `dc` reverse-polish desk calculator, usable as a library.
+//!
+//! A [`Calculator`] holds the stack, the registers and the settings. [`Calculator::eval`] runs
+//! dc source, [`Calculator::execute`] a single [`Op`], and [`parse`] turns source into operations
+//! with their place in it:
+//!
+//! ```
+//! use dc::prelude::*;
+//!
+//! let mut calculator = Calculator::new();
+//! calculator.capture_output();
+//! calculator.eval("2 3+p").unwrap();
+//! assert_eq!(calculator.output(), ["5\n"]);
+//!
+//! let tokens = parse("1 lax", Dialect::Extended).unwrap();
+//! assert_eq!(tokens[1].op, Op::Load('a'));
+//! assert_eq!(tokens[1].span, 2..4);
+//! ```
+//!
+//! The items of the [`prelude`] follow semantic versioning: they only change incompatibly with
+//! a new major version. New operations can be added to [`Op`] in minor versions, so matches on
+//! it need a wildcard arm.
+
 extern crate clap;
 extern crate num_bigint;
 extern crate num_traits;
 
 pub mod calculator;
+pub mod csv;
+pub mod debug;
+pub mod highlight;
+pub mod input;
+pub mod journal;
+pub mod json;
+pub mod meta;
+pub mod output;
 mod token;
 
-use crate::calculator::{Calculator, OpResult, StackValue};
-use crate::token::{tokenize, Op};
-use std::io::Write;
+pub use crate::token::{Comparison, LexError, LexErrorKind, Op, Position, Token};
+
+/// The types and functions needed to embed the calculator, `use dc::prelude::*;` imports them.
+pub mod prelude {
+    pub use crate::calculator::{Calculator, Diagnostic, Dialect, OpResult, StackValue};
+    pub use crate::input::Input;
+    pub use crate::output::Output;
+    pub use crate::{parse, Comparison, LexError, LexErrorKind, Op, Position, Token};
+    pub use num_bigint::BigInt;
+}
+
+use crate::calculator::{Calculator, Diagnostic, Dialect, OpResult, StackValue, Trace};
+use crate::debug::{Control, Debugger, Step};
+use crate::token::{lex, tokenize};
+use num_bigint::BigInt;
+use std::cmp::Ordering;
+use std::process::{Command, Stdio};
+use std::vec::IntoIter;
+
+/// Parses a single literal, a number or a `[string]`, as given on the command line.
+pub fn parse_literal(calculator: &Calculator, str: &str) -> Result<StackValue, String> {
+    match tokenize(str, calculator.dialect())?.as_slice() {
+        [Op::Push(num)] => Ok(StackValue::Number(num.clone())),
+        [Op::PushString(string)] => Ok(StackValue::String(string.clone())),
+        _ => Err(format!("not a number or string: {}", str)),
+    }
+}
+
+/// Parses a number field of some data, like a line of `--each` input. Both `_` and `-` are
+/// accepted as the sign of negative numbers.
+pub fn parse_number(calculator: &Calculator, str: &str) -> Option<BigInt> {
+    let number = match str.strip_prefix('-') {
+        Some(digits) => format!("_{}", digits),
+        None => str.to_owned(),
+    };
+
+    match parse_literal(calculator, &number) {
+        Ok(StackValue::Number(num)) => Some(num),
+        _ => None,
+    }
+}
+
+/// Parses dc source into operations, with the position and the byte range of each in the source.
+///
+/// ```
+/// use dc::prelude::*;
+///
+/// let tokens = parse("[d*]sq\n5 lqx", Dialect::Extended).unwrap();
+/// assert_eq!(tokens[2].op, Op::Push(5.into()));
+/// assert_eq!(tokens[2].position, Position { line: 2, column: 1 });
+///
+/// let err = parse("1 [oops", Dialect::Extended).unwrap_err();
+/// assert_eq!((err.span, err.message.as_str()), (2..7, "Unterminated string: [oops"));
+/// assert_eq!(err.kind, LexErrorKind::UnterminatedString);
+/// ```
+pub fn parse(str: &str, dialect: Dialect) -> Result<Vec<Token>, LexError> {
+    lex(str, dialect)
+}
 
 pub fn process_input(stack: &mut Calculator, str: &str) -> Result<OpResult, String> {
-    let tokens = str
-        .split_whitespace()
-        .map(tokenize)
-        .flatten()
-        .collect::<Vec<Vec<Op>>>()
-        .concat();
-
-    for op in tokens {
-        match process_op(stack, &op) {
+    process(stack, None, str)
+}
+
+/// Same as `process_input`, but errors are reported with the name of the script and the line of
+/// the command that caused them.
+pub fn process_script(calculator: &mut Calculator, name: &str, str: &str) -> Result<OpResult, String> {
+    process(calculator, Some(name), str)
+}
+
+/// Runs operations the same way as the commands of `process_input`.
+pub fn process_ops(calculator: &mut Calculator, ops: Vec<Op>) -> Result<OpResult, String> {
+    let tokens = ops.into_iter().map(|op| Token { op, position: Position::default(), span: 0..0 }).collect();
+    execute(calculator, None, tokens)
+}
+
+/// Runs a single operation for `Calculator::execute`. Its own error is returned, the macro it
+/// runs is executed like input.
+fn execute_op(calculator: &mut Calculator, op: &Op) -> Result<OpResult, String> {
+    match process_op(calculator, op).map_err(Failure::into_message)? {
+        OpResult::Macro(source) => process_input(calculator, &source),
+        result => Ok(result),
+    }
+}
+
+/// Tells whether the input ends inside an unclosed `[` string, so it needs more lines before it
+/// can run.
+pub fn is_unterminated(calculator: &Calculator, str: &str) -> bool {
+    matches!(lex(str, calculator.dialect()), Err(err) if err.kind == LexErrorKind::UnterminatedString)
+}
+
+fn process(calculator: &mut Calculator, name: Option<&str>, str: &str) -> Result<OpResult, String> {
+    match lex(str, calculator.dialect()) {
+        Ok(tokens) => execute(calculator, name, tokens),
+        Err(err) => {
+            report(calculator, name, err.position, err.message);
+            Ok(OpResult::Ok)
+        }
+    }
+}
+
+/// A running piece of code: the input itself or a macro.
+struct Frame {
+    id: usize,
+    tokens: IntoIter<Token>,
+    /// The register the macro was run from, if known.
+    register: Option<char>,
+    /// The register loaded by the previous operation, `lbx` runs the macro of register `b`.
+    loaded: Option<char>,
+    started: bool,
+    /// The registers of the callers dropped by tail calls on the way to this frame.
+    callers: Vec<char>,
+}
+
+impl Frame {
+    fn new(id: usize, tokens: Vec<Token>, register: Option<char>) -> Frame {
+        Frame { id, tokens: tokens.into_iter(), register, loaded: None, started: false, callers: Vec::new() }
+    }
+}
+
+/// Runs the given operations, including any macros they invoke.
+///
+/// Macros are executed on an explicit stack of frames rather than by recursion. When a macro
+/// is invoked by the last operation of the current frame (a tail call), that frame is dropped
+/// before the new one is entered, so recursive loops like `[... lbx]sb lbx` run in constant
+/// memory. The frame of the input itself is always kept, errors are reported at the position of
+/// its command that is being executed.
+///
+/// A debugger set on the calculator is called before every operation.
+fn execute(calculator: &mut Calculator, name: Option<&str>, tokens: Vec<Token>) -> Result<OpResult, String> {
+    let mut debugger = calculator.take_debugger();
+    let result = run_frames(calculator, name, tokens, &mut debugger);
+    calculator.set_debugger(debugger);
+    result
+}
+
+fn run_frames(
+    calculator: &mut Calculator,
+    name: Option<&str>,
+    tokens: Vec<Token>,
+    debugger: &mut Option<Box<dyn Debugger>>,
+) -> Result<OpResult, String> {
+    let mut frames = vec![Frame::new(0, tokens, None)];
+    let mut frame_ids = 1..;
+    let mut position = Position::default();
+
+    while !frames.is_empty() {
+        let depth = frames.len() - 1;
+        let frame = frames.last_mut().unwrap();
+        let token = match frame.tokens.next() {
+            Some(token) => token,
+            None => {
+                frames.pop();
+                continue;
+            }
+        };
+        let tail_call = frame.tokens.len() == 0 && depth > 0;
+        if depth == 0 {
+            position = token.position;
+        }
+
+        if let Some(debugger) = debugger {
+            let step = Step {
+                op: &token.op,
+                position: token.position,
+                source: name,
+                depth,
+                frame: frame.id,
+                register: frame.register,
+                entering: !frame.started,
+            };
+            frame.started = true;
+            if debugger.before(calculator, &step) == Control::Quit {
+                return Ok(OpResult::Exit);
+            }
+        }
+
+        let result = process_op(calculator, &token.op);
+        if calculator.trace().is_some() {
+            trace(calculator, &frames, &token);
+        }
+
+        let frame = frames.last_mut().unwrap();
+        let register = match token.op {
+            Op::Conditional(_, register) => Some(register),
+            Op::Execute => frame.loaded,
+            _ => None,
+        };
+        frame.loaded = match token.op {
+            Op::Load(register) => Some(register),
+            _ => None,
+        };
+
+        match result {
             Ok(OpResult::Exit) => return Ok(OpResult::Exit),
+            Ok(OpResult::Macro(source)) => match lex(&source, calculator.dialect()) {
+                Ok(tokens) => {
+                    let mut callee = Frame::new(frame_ids.next().unwrap(), tokens, register);
+                    if tail_call {
+                        let caller = frames.pop().unwrap();
+                        callee.callers = caller.callers;
+                        callee.callers.extend(caller.register.filter(|register| !callee.callers.contains(register)));
+                    }
+                    frames.push(callee);
+                }
+                Err(err) => report(calculator, name, position, err.message),
+            },
             Ok(_) => (),
-            Err(err) => println!("{}", err),
+            Err(Failure::Error(err)) => report(calculator, name, position, err),
+            Err(Failure::Fatal(err)) => return Err(err),
         };
     }
 
-    Ok(OpResult::Ok)
+    match debugger.as_mut().map(|debugger| debugger.finished(calculator, name)) {
+        Some(Control::Quit) => Ok(OpResult::Exit),
+        _ => Ok(OpResult::Ok),
+    }
+}
+
+/// Logs an executed operation to the output with its position in its own frame, the macro depth
+/// and the top of the stack, if it passes the filters of the trace settings.
+fn trace(calculator: &mut Calculator, frames: &[Frame], token: &Token) {
+    if !is_traced(calculator.trace().unwrap(), frames) {
+        return;
+    }
+
+    let line = format!(
+        "trace {}:{} depth={} macro={} op={} stack=[{}]",
+        token.position.line,
+        token.position.column,
+        frames.len() - 1,
+        frames.last().and_then(|frame| frame.register).map_or("-".to_owned(), |register| register.to_string()),
+        token.op,
+        stack_summary(calculator)
+    );
+    calculator.log_trace(&line);
+}
+
+fn is_traced(settings: &Trace, frames: &[Frame]) -> bool {
+    let depth = frames.len() - 1;
+    let in_register = |frame: &Frame| {
+        frame.register.iter().chain(&frame.callers).any(|register| settings.registers.contains(register))
+    };
+
+    settings.max_depth.is_none_or(|max_depth| depth <= max_depth)
+        && (settings.registers.is_empty() || frames.iter().any(in_register))
+}
+
+/// Shows the top five values of the stack, with long strings shortened.
+fn stack_summary(calculator: &Calculator) -> String {
+    let count = calculator.iter().count();
+    let mut top = calculator
+        .iter()
+        .skip(count.saturating_sub(5))
+        .map(|value| match value {
+            StackValue::Number(num) => num.to_string().replace('-', "_"),
+            StackValue::String(string) if string.chars().count() > 20 => {
+                format!("[{}...]", string.chars().take(20).collect::<String>())
+            }
+            StackValue::String(string) => format!("[{}]", string),
+        })
+        .collect::<Vec<String>>();
+    if count > 5 {
+        top.insert(0, "...".to_owned());
+    }
+
+    top.join(" ")
+}
+
+fn report(calculator: &mut Calculator, name: Option<&str>, position: Position, message: String) {
+    calculator.report(Diagnostic {
+        source: name.map(|name| name.to_owned()),
+        line: position.line,
+        column: position.column,
+        message,
+    });
+}
+
+/// Why an operation failed. An `Error` is reported and the execution goes on, a `Fatal` one,
+/// such as output that can no longer be written, ends the run.
+enum Failure {
+    Error(String),
+    Fatal(String),
+}
+
+impl Failure {
+    fn into_message(self) -> String {
+        match self {
+            Failure::Error(message) | Failure::Fatal(message) => message,
+        }
+    }
+}
+
+impl From<String> for Failure {
+    fn from(message: String) -> Failure {
+        Failure::Error(message)
+    }
 }
 
-fn process_op(calculator: &mut Calculator, op: &Op) -> Result<OpResult, String> {
-    match op {
-        Op::GetInputRadix => calculator.get_input_radix(),
-        Op::GetOutputRadix => calculator.get_output_radix(),
-        Op::GetPrecision => calculator.get_precision(),
-        Op::SetInputRadix => calculator.set_input_radix(),
-        Op::SetOutputRadix => calculator.set_output_radix(),
-        Op::SetPrecision => calculator.set_precision(),
+fn process_op(calculator: &mut Calculator, op: &Op) -> Result<OpResult, Failure> {
+    let result = match op {
+        Op::GetInputRadix => calculator.push_input_radix(),
+        Op::GetOutputRadix => calculator.push_output_radix(),
+        Op::GetPrecision => calculator.push_precision(),
+        Op::SetInputRadix => calculator.pop_input_radix(),
+        Op::SetOutputRadix => calculator.pop_output_radix(),
+        Op::SetPrecision => calculator.pop_precision(),
         Op::Exit => Ok(OpResult::Exit),
-        Op::PrintPeek => calculator
-            .peek()
-            .map(|value| {
-                println!("{}", value);
-                OpResult::Ok
-            })
-            .ok_or("stack empty!".to_owned()),
+        Op::PrintPeek => {
+            let text = calculator.peek().map(|value| format!("{}\n", calculator.format(value))).ok_or_else(|| "stack empty!".to_owned())?;
+            return print(calculator, text);
+        }
         Op::Clear => calculator.clear(),
         Op::PrintAll => {
-            calculator.iter().for_each(|value| println!("{}", value));
+            let text = calculator.iter().map(|value| format!("{}\n", calculator.format(value))).collect::<String>();
+            return print(calculator, text);
+        }
+        Op::PrintPop => {
+            let text = calculator.peek().map(|value| calculator.format(value)).ok_or_else(|| "stack empty!".to_owned())?;
+            print(calculator, text)?;
+            calculator.pop();
             Ok(OpResult::Ok)
         }
-        Op::PrintPop => calculator
-            .pop()
-            .map(|value| {
-                print!("{}", value);
-                std::io::stdout().flush().unwrap();
-                OpResult::Ok
-            })
-            .ok_or("stack empty!".to_owned()),
         Op::Duplicate => {
-            let value = calculator.peek().and_then(|value| match value {
-                StackValue::Number(num) => Some(num),
-            });
-
-            if let Some(num) = value {
-                let stack_value = StackValue::Number(num.clone());
-                calculator.push(stack_value)
+            if let Some(value) = calculator.peek().cloned() {
+                calculator.push(value)
+            } else if calculator.dialect().is_strict() {
+                Err("stack empty!".to_owned())
             } else {
                 Ok(OpResult::Ok)
             }
         }
         Op::Reverse => calculator.reverse(),
+        Op::Rotate => calculator.rotate(),
+        Op::Drop => calculator
+            .pop()
+            .map(|_| OpResult::Ok)
+            .ok_or_else(|| "stack empty!".to_owned()),
         Op::Add => calculator.add(),
         Op::Sub => calculator.sub(),
         Op::Mul => calculator.mul(),
@@ -79,15 +382,69 @@ fn process_op(calculator: &mut Calculator, op: &Op) -> Result<OpResult, String>
         Op::Exp => calculator.exp(),
         Op::Sqrt => calculator.sqrt(),
         Op::ModExp => calculator.mod_exp(),
-        Op::Push(num) => calculator.push(StackValue::Number(num.clone())),
+        Op::Push(num) => calculator.push(calculator.number(num.clone())),
+        Op::PushString(string) => calculator.push(StackValue::String(string.clone())),
+        Op::Store(register) => calculator.store(*register),
+        Op::Load(register) => calculator.load(*register),
+        Op::PushRegister(register) => calculator.push_register(*register),
+        Op::PopRegister(register) => calculator.pop_register(*register),
+        Op::StoreArray(register) => calculator.store_array(*register),
+        Op::LoadArray(register) => calculator.load_array(*register),
+        Op::StackDepth => calculator.stack_depth(),
+        Op::Execute => calculator.execute_top(),
+        Op::Read => Ok(calculator.read_line()?.map_or(OpResult::Ok, OpResult::Macro)),
+        Op::Shell(command) => return run_shell(calculator, command),
+        Op::Conditional(comparison, register) => {
+            calculator.execute_if(*register, |ordering| match comparison {
+                Comparison::Less => ordering == Ordering::Less,
+                Comparison::Greater => ordering == Ordering::Greater,
+                Comparison::Equal => ordering == Ordering::Equal,
+                Comparison::NotLess => ordering != Ordering::Less,
+                Comparison::NotGreater => ordering != Ordering::Greater,
+                Comparison::NotEqual => ordering != Ordering::Equal,
+            })
+        }
+        Op::Unimplemented(ch) => Err(format!("'{}' (0{:o}) unimplemented", ch, *ch as u32)),
+        Op::Unsupported(ch) => Err(format!(
+            "'{}' (0{:o}) is not supported in the {} dialect",
+            ch,
+            *ch as u32,
+            calculator.dialect()
+        )),
+    };
+    result.map_err(Failure::Error)
+}
+
+/// Writes the text of a printing command. Failing to is fatal, as later output would be lost too.
+fn print(calculator: &mut Calculator, text: String) -> Result<OpResult, Failure> {
+    calculator.print(text).map(|_| OpResult::Ok).map_err(Failure::Fatal)
+}
+
+/// Runs a `!` command with `sh`, printing what it writes to stdout like a printing command.
+fn run_shell(calculator: &mut Calculator, command: &str) -> Result<OpResult, Failure> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|err| format!("could not run {}: {}", command, err))?;
+    if !output.stdout.is_empty() {
+        print(calculator, String::from_utf8_lossy(&output.stdout).into_owned())?;
     }
+    Ok(OpResult::Ok)
 }
 
 #[cfg(test)]
 mod test {
-    use crate::calculator::{Calculator, StackValue};
-    use crate::process_input;
+    use crate::calculator::{Calculator, Dialect, OpResult, StackValue, Trace};
+    use crate::debug::{Control, Debugger, Step};
+    use crate::input::Queue;
+    use crate::journal::Entry;
+    use crate::{is_traced, is_unterminated, parse_literal, parse_number, process_input, process_ops, process_script, stack_summary, Frame, Op};
     use num_bigint::BigInt;
+    use std::cell::RefCell;
+    use std::rc::Rc;
 
     #[test]
     fn test_execution_empty() {
@@ -125,4 +482,279 @@ mod test {
         process_input(&mut calculator, "5d*").unwrap();
         assert_eq!(*calculator.peek().unwrap(), StackValue::Number(BigInt::from(25)));
     }
+
+    #[test]
+    fn test_execution_unimplemented_continues() {
+        let mut calculator = Calculator::new();
+        process_input(&mut calculator, "5y 3+").unwrap();
+        assert_eq!(*calculator.peek().unwrap(), StackValue::Number(BigInt::from(8)));
+    }
+
+    #[test]
+    fn test_execution_posix_dialect() {
+        let mut calculator = Calculator::new();
+        calculator.set_dialect(Dialect::Posix);
+        calculator.capture_output();
+        process_input(&mut calculator, "7 2~ 3+ 1 !<a").unwrap();
+        assert_eq!(*calculator.peek().unwrap(), StackValue::Number(BigInt::from(1)));
+        assert_eq!(calculator.iter().count(), 3);
+        assert_eq!(calculator.errors()[1].message, "'!' (041) is not supported in the posix dialect");
+        assert!(calculator.output().is_empty());
+    }
+
+    #[test]
+    fn test_execution_strict_duplicate() {
+        let mut calculator = Calculator::new();
+        calculator.capture_output();
+        calculator.set_dialect(Dialect::Gnu);
+        assert_eq!(process_input(&mut calculator, "d"), Ok(OpResult::Ok));
+        assert_eq!(calculator.peek(), None);
+        assert_eq!(calculator.errors().len(), 1);
+        assert_eq!(calculator.errors()[0].message, "stack empty!");
+
+        calculator.set_dialect(Dialect::Extended);
+        process_input(&mut calculator, "d").unwrap();
+        assert_eq!(calculator.errors().len(), 1);
+    }
+
+    #[test]
+    fn test_script_error_lines() {
+        let mut calculator = Calculator::new();
+        calculator.capture_output();
+        process_script(&mut calculator, "sb.dc", "#!/usr/bin/dc -f\n# divide\n1 0/\n").unwrap();
+        assert_eq!(calculator.errors()[0].to_string(), "sb.dc:3: division by zero");
+    }
+
+    #[test]
+    fn test_execution_shell() {
+        let mut calculator = Calculator::new();
+        calculator.capture_output();
+        calculator.set_dialect(Dialect::Gnu);
+        process_input(&mut calculator, "1 !echo hi\np").unwrap();
+        assert_eq!(calculator.output(), ["hi\n", "1\n"]);
+
+        calculator.set_dialect(Dialect::Extended);
+        process_input(&mut calculator, "!echo hi").unwrap();
+        assert_eq!(calculator.output().len(), 2);
+        assert_eq!(calculator.errors()[0].message, "'!' (041) unimplemented");
+    }
+
+    #[test]
+    fn test_parse_literal() {
+        let calculator = Calculator::new();
+        assert_eq!(parse_literal(&calculator, "_12"), Ok(StackValue::Number(BigInt::from(-12))));
+        assert_eq!(parse_literal(&calculator, "[a b]"), Ok(StackValue::String("a b".to_owned())));
+        assert_eq!(parse_literal(&calculator, "1 2"), Err("not a number or string: 1 2".to_owned()));
+        assert_eq!(parse_literal(&calculator, "p"), Err("not a number or string: p".to_owned()));
+    }
+
+    #[test]
+    fn test_parse_number() {
+        let calculator = Calculator::new();
+        assert_eq!(parse_number(&calculator, "42"), Some(BigInt::from(42)));
+        assert_eq!(parse_number(&calculator, "_42"), Some(BigInt::from(-42)));
+        assert_eq!(parse_number(&calculator, "-42"), Some(BigInt::from(-42)));
+        assert_eq!(parse_number(&calculator, "[42]"), None);
+        assert_eq!(parse_number(&calculator, "GET"), None);
+        assert_eq!(parse_number(&calculator, "4-2"), None);
+    }
+
+    #[test]
+    fn test_execution_arrays() {
+        let mut calculator = Calculator::new();
+        process_input(&mut calculator, "[a]0:r 7 1:r 1;r 0;r z").unwrap();
+        assert_eq!(*calculator.peek().unwrap(), StackValue::Number(BigInt::from(2)));
+    }
+
+    #[test]
+    fn test_execution_captured() {
+        let mut calculator = Calculator::new();
+        calculator.capture_output();
+        process_input(&mut calculator, "1p 2 3f n\n4 0/").unwrap();
+        assert_eq!(calculator.output(), &["1\n".to_owned(), "1\n2\n3\n".to_owned(), "3".to_owned()]);
+        assert_eq!(calculator.errors().len(), 1);
+        assert_eq!(calculator.errors()[0].to_string(), "division by zero");
+        assert_eq!((calculator.errors()[0].line, calculator.errors()[0].column), (2, 4));
+    }
+
+    #[test]
+    fn test_unterminated() {
+        let calculator = Calculator::new();
+        assert!(is_unterminated(&calculator, "1 [2 ["));
+        assert!(is_unterminated(&calculator, "[d*\n]sq [a"));
+        assert!(!is_unterminated(&calculator, "[d*\n]sq"));
+        assert!(!is_unterminated(&calculator, "1 s[ 2 # [comment"));
+    }
+
+    #[test]
+    fn test_trace_filters() {
+        let frames = vec![Frame::new(0, vec![], None), Frame::new(1, vec![], Some('a')), Frame::new(2, vec![], None)];
+        assert!(is_traced(&Trace::default(), &frames));
+        assert!(is_traced(&Trace { registers: vec!['a'], max_depth: None }, &frames));
+        assert!(!is_traced(&Trace { registers: vec!['b'], max_depth: None }, &frames));
+        assert!(is_traced(&Trace { registers: vec![], max_depth: Some(2) }, &frames));
+        assert!(!is_traced(&Trace { registers: vec![], max_depth: Some(1) }, &frames));
+        assert!(!is_traced(&Trace { registers: vec!['a'], max_depth: None }, &frames[..1]));
+
+        let mut callee = Frame::new(3, vec![], Some('b'));
+        callee.callers = vec!['a'];
+        let frames = vec![Frame::new(0, vec![], None), callee];
+        assert!(is_traced(&Trace { registers: vec!['a'], max_depth: None }, &frames));
+    }
+
+    #[test]
+    fn test_stack_summary() {
+        let mut calculator = Calculator::new();
+        process_input(&mut calculator, "_1 [abcdefghijklmnopqrstuvwxyz]").unwrap();
+        assert_eq!(stack_summary(&calculator), "_1 [abcdefghijklmnopqrst...]");
+        process_input(&mut calculator, "c 1 2 3 4 5 6").unwrap();
+        assert_eq!(stack_summary(&calculator), "... 2 3 4 5 6");
+    }
+
+    #[test]
+    fn test_read_input() {
+        let mut calculator = Calculator::new();
+        calculator.record();
+        calculator.set_input(Box::new(Queue::new(vec!["2 3".to_owned(), "*p".to_owned()])));
+        assert_eq!(process_input(&mut calculator, "? ? ?"), Ok(OpResult::Ok));
+        assert_eq!(calculator.peek(), Some(&StackValue::Number(BigInt::from(6))));
+        assert_eq!(
+            calculator.journal().unwrap().entries,
+            vec![Entry::Read("2 3".to_owned()), Entry::Read("*p".to_owned()), Entry::Output("6\n".to_owned())]
+        );
+    }
+
+    /// Logs every step as `depth:frame:register:op`, with a `>` when entering a frame.
+    struct StepLog {
+        steps: Rc<RefCell<Vec<String>>>,
+        quit_at: Option<usize>,
+    }
+
+    impl Debugger for StepLog {
+        fn before(&mut self, _calculator: &mut Calculator, step: &Step<'_>) -> Control {
+            let mut steps = self.steps.borrow_mut();
+            steps.push(format!(
+                "{}{}:{}:{}:{}",
+                if step.entering { ">" } else { "" },
+                step.depth,
+                step.frame,
+                step.register.unwrap_or('-'),
+                step.op
+            ));
+            if self.quit_at == Some(steps.len()) {
+                Control::Quit
+            } else {
+                Control::Run
+            }
+        }
+
+        fn finished(&mut self, _calculator: &mut Calculator, source: Option<&str>) -> Control {
+            self.steps.borrow_mut().push(format!("end:{}", source.unwrap_or("-")));
+            Control::Run
+        }
+    }
+
+    #[test]
+    fn test_debugger_steps() {
+        let steps = Rc::new(RefCell::new(Vec::new()));
+        let mut calculator = Calculator::new();
+        calculator.set_debugger(Some(Box::new(StepLog { steps: steps.clone(), quit_at: None })));
+        process_input(&mut calculator, "[1+]sa 0 lax lax").unwrap();
+        assert_eq!(
+            *steps.borrow(),
+            vec![
+                ">0:0:-:[1+]", "0:0:-:sa", "0:0:-:0", "0:0:-:la", "0:0:-:x",
+                ">1:1:a:1", "1:1:a:+",
+                "0:0:-:la", "0:0:-:x",
+                ">1:2:a:1", "1:2:a:+",
+                "end:-",
+            ]
+        );
+        assert_eq!(calculator.peek(), Some(&StackValue::Number(BigInt::from(2))));
+    }
+
+    #[test]
+    fn test_debugger_tail_call() {
+        let steps = Rc::new(RefCell::new(Vec::new()));
+        let mut calculator = Calculator::new();
+        process_input(&mut calculator, "[lbx]sa [2]sb").unwrap();
+        calculator.set_debugger(Some(Box::new(StepLog { steps: steps.clone(), quit_at: None })));
+        process_script(&mut calculator, "t.dc", "lax p").unwrap();
+        assert_eq!(
+            *steps.borrow(),
+            vec![">0:0:-:la", "0:0:-:x", ">1:1:a:lb", "1:1:a:x", ">1:2:b:2", "0:0:-:p", "end:t.dc"]
+        );
+    }
+
+    #[test]
+    fn test_debugger_quit() {
+        let steps = Rc::new(RefCell::new(Vec::new()));
+        let mut calculator = Calculator::new();
+        calculator.set_debugger(Some(Box::new(StepLog { steps: steps.clone(), quit_at: Some(2) })));
+        assert_eq!(process_input(&mut calculator, "1 2 3"), Ok(OpResult::Exit));
+        assert_eq!(steps.borrow().len(), 2);
+        assert_eq!(calculator.iter().count(), 1);
+
+        process_input(&mut calculator, "4").unwrap();
+        assert_eq!(steps.borrow()[2..], [">0:0:-:4", "end:-"]);
+    }
+
+    #[test]
+    fn test_process_ops() {
+        let mut calculator = Calculator::new();
+        process_input(&mut calculator, "1 2 [3+]").unwrap();
+        assert_eq!(process_ops(&mut calculator, vec![Op::Drop, Op::Reverse, Op::Duplicate]), Ok(OpResult::Ok));
+        assert_eq!(calculator.iter().map(|value| value.to_string()).collect::<Vec<String>>(), vec!["2", "1", "1"]);
+        assert_eq!(process_ops(&mut calculator, vec![Op::PushString("2*".to_owned()), Op::Execute]), Ok(OpResult::Ok));
+        assert_eq!(calculator.peek(), Some(&StackValue::Number(BigInt::from(2))));
+    }
+
+    #[test]
+    fn test_execution_macro() {
+        let mut calculator = Calculator::new();
+        process_input(&mut calculator, "[2 3+]x").unwrap();
+        assert_eq!(*calculator.peek().unwrap(), StackValue::Number(BigInt::from(5)));
+    }
+
+    #[test]
+    fn test_execution_number_executed_as_macro() {
+        let mut calculator = Calculator::new();
+        process_input(&mut calculator, "42x").unwrap();
+        assert_eq!(*calculator.peek().unwrap(), StackValue::Number(BigInt::from(42)));
+    }
+
+    #[test]
+    fn test_execution_registers() {
+        let mut calculator = Calculator::new();
+        process_input(&mut calculator, "1sa 2Sa la 3*").unwrap();
+        assert_eq!(*calculator.peek().unwrap(), StackValue::Number(BigInt::from(6)));
+        process_input(&mut calculator, "La La+").unwrap();
+        assert_eq!(*calculator.peek().unwrap(), StackValue::Number(BigInt::from(3)));
+    }
+
+    #[test]
+    fn test_execution_conditional() {
+        let mut calculator = Calculator::new();
+        process_input(&mut calculator, "[42]sa 1 2>a").unwrap();
+        assert_eq!(*calculator.peek().unwrap(), StackValue::Number(BigInt::from(42)));
+        process_input(&mut calculator, "c 2 1>a").unwrap();
+        assert_eq!(calculator.peek(), None);
+        process_input(&mut calculator, "2 1!>a").unwrap();
+        assert_eq!(*calculator.peek().unwrap(), StackValue::Number(BigInt::from(42)));
+    }
+
+    #[test]
+    fn test_execution_nested_macro() {
+        let mut calculator = Calculator::new();
+        process_input(&mut calculator, "[3*]sa [lax 1+]sb 2 lbx").unwrap();
+        assert_eq!(*calculator.peek().unwrap(), StackValue::Number(BigInt::from(7)));
+    }
+
+    #[test]
+    fn test_execution_tail_recursive_loop() {
+        let mut calculator = Calculator::new();
+        // Counts to a million; only works in constant memory thanks to tail calls
+        process_input(&mut calculator, "0 [1+ d 1000000>b]sb lbx").unwrap();
+        assert_eq!(*calculator.peek().unwrap(), StackValue::Number(BigInt::from(1_000_000)));
+    }
 }
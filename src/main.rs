@@ -1,29 +1,420 @@
-use clap::{App, Arg, ArgMatches};
-use dc::calculator::{Calculator, OpResult};
-use dc::process_input;
+use clap::{App, Arg, ArgMatches, Error, ErrorKind};
+use num_bigint::BigInt;
+use dc::calculator::{Calculator, Dialect, OpResult, StackValue, Trace};
+use dc::input::Queue;
+use dc::output::{Output, Stdout};
+use dc::journal::{Entry, Journal, Source};
+use dc::meta::{self, Command};
+use dc::{csv, is_unterminated, json, parse_literal, parse_number, process_input, process_script, Op};
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use rustyline::{CompletionType, Config, Editor};
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+mod debugger;
+mod editor;
+mod tui;
+
+use editor::DcHelper;
+
+/// A script given on the command line with `-e` or `-f`.
+enum Script {
+    Expression(String),
+    File(String),
+}
 
 fn main() {
     let matches = parse_arguments();
+    if matches.is_present("watch") {
+        watch(&matches);
+        return;
+    }
+    if let Some(file) = matches.value_of("replay") {
+        std::process::exit(replay(&matches, file));
+    }
+
+    let mut calculator = new_calculator(&matches);
+    if matches.is_present("json") {
+        calculator.capture_output();
+    }
+
+    if run(&mut calculator, &matches) == OpResult::Ok {
+        if let Some(program) = matches.value_of("each") {
+            each(&mut calculator, &matches, program);
+        } else if let Some(program) = matches.value_of("csv") {
+            csv(&mut calculator, &matches, program);
+        } else if matches.is_present("tui") {
+            if let Err(err) = tui::run(&mut calculator) {
+                eprintln!("dc: {}", err);
+            }
+        } else if matches.values_of("FILE").is_none() {
+            repl(&mut calculator);
+        }
+    }
+
+    if let Some(file) = matches.value_of("session") {
+        if let Err(err) = save_session(&calculator, file) {
+            eprintln!("dc: Could not save session to {}: {}", file, err);
+        }
+    }
+
+    if let (Some(file), Some(journal)) = (matches.value_of("record"), calculator.journal()) {
+        if let Err(err) = fs::write(file, journal.to_string()) {
+            eprintln!("dc: Could not write journal to {}: {}", file, err);
+        }
+    }
+
+    if matches.is_present("json") {
+        print_stdout(&format!("{}\n", json::to_json(&calculator)));
+    }
+}
+
+fn new_calculator(matches: &ArgMatches) -> Calculator {
     let mut calculator = Calculator::new();
+    if let Some(value) = matches.value_of("dialect") {
+        calculator.set_dialect(value.parse::<Dialect>().unwrap());
+    }
+    if matches.is_present("trace") {
+        calculator.set_trace(Some(Trace {
+            registers: matches.value_of("trace-registers").map_or(vec![], |registers| registers.chars().collect()),
+            max_depth: matches.value_of("trace-depth").map(|depth| depth.parse().unwrap()),
+        }));
+    }
+    if matches.is_present("record") {
+        calculator.record();
+    }
+    calculator
+}
+
+/// Runs the startup file, the scripts and the files given on the command line, in this order.
+/// With `--debug`, the debugger starts after the startup file.
+fn run(calculator: &mut Calculator, matches: &ArgMatches) -> OpResult {
+    if let Some(file) = matches.value_of("session") {
+        load_session(calculator, file);
+    }
+    if !matches.is_present("no-rc") {
+        if let Some(file) = rc_file() {
+            if process_file(calculator, &file.to_string_lossy()) == OpResult::Exit {
+                return OpResult::Exit;
+            }
+        }
+    }
+    if let Some(values) = matches.values_of("ARGS") {
+        let register = matches.value_of("args-register").and_then(|name| name.chars().next());
+        if let Err(err) = push_arguments(calculator, values.collect(), register) {
+            eprintln!("dc: {}", err);
+            std::process::exit(1);
+        }
+    }
+
+    if matches.is_present("debug") {
+        calculator.set_debugger(Some(Box::new(debugger::Session::new())));
+    }
+
+    for script in scripts(matches) {
+        let result = match script {
+            Script::Expression(value) => {
+                calculator.record_entry(Entry::Input(Source::Expression, value.clone()));
+                checked(process_input(calculator, &value))
+            }
+            Script::File(file) => process_file(calculator, &file),
+        };
+        if result == OpResult::Exit {
+            return OpResult::Exit;
+        }
+    }
+
+    for file in matches.values_of("FILE").into_iter().flatten() {
+        if process_file(calculator, file) == OpResult::Exit {
+            return OpResult::Exit;
+        }
+    }
+
+    OpResult::Ok
+}
 
-    if let Some(value) = matches.value_of("expression") {
-        process_input(&mut calculator, value).unwrap();
-    } else if let Some(value) = matches.value_of("file") {
-        let content = std::fs::read_to_string(value).unwrap();
-        process_input(&mut calculator, &content).unwrap();
-    } else if let Some(files) = matches.values_of("FILE") {
-        for file in files {
-            let content = std::fs::read_to_string(file).unwrap();
-            process_input(&mut calculator, &content).unwrap();
+/// Runs a journal recorded with `--record` on a new calculator, feeding `?` the recorded lines,
+/// and checks that every input prints exactly what it printed when it was recorded. Returns the
+/// exit code: 1 if the journal is invalid or any output differs.
+fn replay(matches: &ArgMatches, file: &str) -> i32 {
+    let journal = match fs::read_to_string(file).map_err(|err| err.to_string()).and_then(|text| Journal::parse(&text)) {
+        Ok(journal) => journal,
+        Err(err) => {
+            eprintln!("dc: Could not read journal {}: {}", file, err);
+            return 1;
         }
+    };
+
+    let mut calculator = new_calculator(matches);
+    calculator.capture_output();
+    let starts = journal
+        .entries
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| matches!(entry, Entry::Input(..) | Entry::Command(_)))
+        .map(|(index, _)| index)
+        .collect::<Vec<usize>>();
+
+    let mut differences = 0;
+    for (number, &start) in starts.iter().enumerate() {
+        let end = starts.get(number + 1).copied().unwrap_or(journal.entries.len());
+        let recorded = &journal.entries[start + 1..end];
+        let reads = recorded
+            .iter()
+            .filter_map(|entry| match entry {
+                Entry::Read(line) => Some(line.clone()),
+                _ => None,
+            })
+            .collect();
+        calculator.set_input(Box::new(Queue::new(reads)));
+
+        let printed = calculator.output().len();
+        let reported = calculator.errors().len();
+        let result = match &journal.entries[start] {
+            Entry::Input(Source::File(name), text) => process_script(&mut calculator, name, text),
+            Entry::Input(Source::Repl, text) => {
+                calculator.checkpoint();
+                process_input(&mut calculator, text)
+            }
+            Entry::Input(_, text) => process_input(&mut calculator, text),
+            Entry::Command(command) => match meta::parse(command) {
+                Some(Ok(command)) => Ok(run_command(&mut calculator, command).unwrap_or(OpResult::Ok)),
+                _ => Err(format!("unknown command: {}", command)),
+            },
+            _ => unreachable!(),
+        };
+
+        let output = calculator.output()[printed..].concat();
+        print_stdout(&output);
+        for diagnostic in &calculator.errors()[reported..] {
+            Stdout.report(diagnostic).ok();
+        }
+        let expected = Journal::output(recorded);
+        if output != expected {
+            differences += 1;
+            eprintln!("dc: input {} printed {:?} instead of {:?}", number + 1, output, expected);
+        }
+
+        match result {
+            Ok(OpResult::Exit) => break,
+            Ok(_) => (),
+            Err(err) => {
+                eprintln!("dc: {}", err);
+                break;
+            }
+        }
+    }
+
+    if differences > 0 {
+        eprintln!("dc: {} of {} inputs printed something else than when recorded", differences, starts.len());
+        1
     } else {
-        repl(&mut calculator);
+        0
+    }
+}
+
+/// Restores a session saved by an earlier run. A missing file starts a new session, an invalid
+/// one stops dc, so it is not overwritten on exit.
+fn load_session(calculator: &mut Calculator, file: &str) {
+    let loaded = match fs::File::open(file) {
+        Ok(session) => calculator.load_from(session),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err),
+    };
+    if let Err(err) = loaded {
+        eprintln!("dc: Could not load session from {}: {}", file, err);
+        std::process::exit(1);
+    }
+}
+
+/// Saves the session into a temporary file next to it, which only replaces the old session once
+/// it is completely written. A failed save leaves the old session as it was.
+fn save_session(calculator: &Calculator, file: &str) -> io::Result<()> {
+    let mut temporary = PathBuf::from(file).into_os_string();
+    temporary.push(".tmp");
+    let mut writer = io::BufWriter::new(fs::File::create(&temporary)?);
+    let saved = calculator
+        .save_to(&mut writer)
+        .and_then(|_| writer.flush())
+        .and_then(|_| writer.get_ref().sync_all())
+        .and_then(|_| fs::rename(&temporary, file));
+    if saved.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+    saved
+}
+
+/// Runs the program for every line of the standard input, awk style. The numbers on the line
+/// are pushed onto the stack, other fields are ignored. Registers are kept between the lines, so
+/// the `--begin` and `--end` programs can be used to aggregate.
+fn each(calculator: &mut Calculator, matches: &ArgMatches, program: &str) {
+    if let Some(begin) = matches.value_of("begin") {
+        if checked(process_input(calculator, begin)) == OpResult::Exit {
+            return;
+        }
+    }
+
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                eprintln!("dc: could not read input: {}", err);
+                std::process::exit(1);
+            }
+        };
+
+        if !matches.is_present("keep") {
+            calculator.clear().unwrap();
+        }
+        let numbers = line
+            .split_whitespace()
+            .filter_map(|field| parse_number(calculator, field))
+            .collect::<Vec<BigInt>>();
+        for num in numbers {
+            calculator.push(StackValue::Number(num)).unwrap();
+        }
+        if checked(process_input(calculator, program)) == OpResult::Exit {
+            return;
+        }
+    }
+
+    if let Some(end) = matches.value_of("end") {
+        checked(process_input(calculator, end));
     }
 }
 
+/// Runs the program for every row of the CSV standard input. The numeric fields of the row are
+/// stored as numbers and the others as strings in the array of register `c` (or pushed onto the
+/// stack), and whatever is left on the stack is written as the output row. Rows that leave the
+/// stack empty are dropped.
+fn csv(calculator: &mut Calculator, matches: &ArgMatches, program: &str) {
+    let mut input = String::new();
+    let records = io::stdin()
+        .read_to_string(&mut input)
+        .map_err(|err| err.to_string())
+        .and_then(|_| csv::parse(&input));
+    let mut records = match records {
+        Ok(records) => records.into_iter(),
+        Err(err) => {
+            eprintln!("dc: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let header = if matches.is_present("csv-header") {
+        records.next()
+    } else {
+        None
+    };
+    let names = matches
+        .value_of("csv-names")
+        .map(|names| names.split(',').map(|name| name.to_owned()).collect())
+        .or(header);
+    if let Some(names) = names {
+        print(calculator, csv::format_record(&names));
+    }
+
+    let records = records.collect::<Vec<Vec<String>>>();
+    calculator.set_scale(match matches.value_of("csv-scale") {
+        Some(scale) => scale.parse().unwrap(),
+        None => csv::scale(&records),
+    });
+
+    if let Some(begin) = matches.value_of("begin") {
+        if checked(process_input(calculator, begin)) == OpResult::Exit {
+            return;
+        }
+    }
+
+    for (number, record) in records.iter().enumerate() {
+        if let Err(err) = csv::load_record(calculator, record, matches.is_present("csv-stack")) {
+            eprintln!("dc: row {}: {}", number + 1, err);
+            std::process::exit(1);
+        }
+
+        let result = checked(process_input(calculator, program));
+        let row = csv::output_record(calculator);
+        if !row.is_empty() {
+            print(calculator, csv::format_record(&row));
+        }
+        if result == OpResult::Exit {
+            return;
+        }
+    }
+
+    if let Some(end) = matches.value_of("end") {
+        checked(process_input(calculator, end));
+    }
+}
+
+/// Writes text to the output of the calculator, so `--json` collects it with the printed text.
+/// Like for the printing commands, failing to write stops dc.
+fn print(calculator: &mut Calculator, text: String) {
+    if let Err(err) = calculator.print(text) {
+        eprintln!("dc: {}", err);
+        std::process::exit(1);
+    }
+}
+
+/// Writes text to stdout without going through a calculator, such as the output of a finished
+/// run. Failing to write stops dc.
+fn print_stdout(text: &str) {
+    if let Err(err) = Stdout.print(text) {
+        eprintln!("dc: could not write output: {}", err);
+        std::process::exit(1);
+    }
+}
+
+/// Runs everything with a fresh calculator, and again whenever one of the script files changes.
+fn watch(matches: &ArgMatches) {
+    let mut files = scripts(matches)
+        .into_iter()
+        .filter_map(|script| match script {
+            Script::File(file) => Some(PathBuf::from(file)),
+            Script::Expression(_) => None,
+        })
+        .chain(matches.values_of("FILE").into_iter().flatten().map(PathBuf::from))
+        .filter(|file| file.as_os_str() != "-")
+        .collect::<Vec<PathBuf>>();
+    if !matches.is_present("no-rc") {
+        files.extend(rc_file());
+    }
+    if files.is_empty() {
+        eprintln!("dc: --watch needs a script file to watch");
+        std::process::exit(1);
+    }
+
+    loop {
+        let modified = modification_times(&files);
+
+        let mut calculator = new_calculator(matches);
+        print(&mut calculator, "\x1b[2J\x1b[H".to_owned());
+        run(&mut calculator, matches);
+        let listing = calculator.iter().map(|value| format!("{}\n", value)).collect::<String>();
+        print(&mut calculator, format!("--- stack ---\n{}", listing));
+
+        while modification_times(&files) == modified {
+            thread::sleep(Duration::from_millis(500));
+        }
+    }
+}
+
+fn modification_times(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files
+        .iter()
+        .map(|file| fs::metadata(file).and_then(|metadata| metadata.modified()).ok())
+        .collect()
+}
+
 fn parse_arguments() -> ArgMatches<'static> {
-    App::new("dc")
-        .version("0.1")
+    let matches = App::new("dc")
+        .version("0.2")
         .author("Ferenc Nagy <nagy.ferenc.jr@protonmail.com>")
         .about("Clone of the Unix program called dc")
         .arg(Arg::with_name("expression")
@@ -32,6 +423,8 @@ fn parse_arguments() -> ArgMatches<'static> {
             .value_name("scriptexpression")
             .help("Add the commands in script to the set of commands to be run while processing the input.")
             .required(false)
+            .multiple(true)
+            .number_of_values(1)
             .takes_value(true))
         .arg(Arg::with_name("file")
             .short("f")
@@ -39,31 +432,491 @@ fn parse_arguments() -> ArgMatches<'static> {
             .value_name("script-file")
             .help("Add the commands contained in the file script-file to the set of commands to be run while processing the input.")
             .required(false)
+            .multiple(true)
+            .number_of_values(1)
+            .takes_value(true))
+        .arg(Arg::with_name("dialect")
+            .long("dialect")
+            .value_name("dialect")
+            .help("Select the dc flavour to emulate: posix rejects non-POSIX commands, gnu follows GNU dc and extended enables everything this dc supports.")
+            .possible_values(&["posix", "gnu", "extended"])
+            .default_value("extended")
+            .takes_value(true))
+        .arg(Arg::with_name("each")
+            .long("each")
+            .value_name("program")
+            .help("Run the program for every line of the standard input, with the numbers of the line pushed onto an empty stack.")
+            .takes_value(true))
+        .arg(Arg::with_name("csv")
+            .long("csv")
+            .value_name("program")
+            .help("Run the program for every row of the CSV standard input, with the fields in the array of register c. The values left on the stack are written as a CSV row.")
+            .conflicts_with("each")
+            .takes_value(true))
+        .arg(Arg::with_name("csv-stack")
+            .long("csv-stack")
+            .help("Push the fields of the --csv rows onto the stack instead of storing them in register c.")
+            .requires("csv"))
+        .arg(Arg::with_name("csv-header")
+            .long("csv-header")
+            .help("The first row of the --csv input is a header, it is written to the output unchanged.")
+            .requires("csv"))
+        .arg(Arg::with_name("csv-names")
+            .long("csv-names")
+            .value_name("names")
+            .help("Write a header row with these comma separated names before the --csv output, replacing the input header.")
+            .requires("csv")
+            .takes_value(true))
+        .arg(Arg::with_name("csv-scale")
+            .long("csv-scale")
+            .value_name("digits")
+            .help("Compute the --csv numbers with this many decimals, and write the results with them. By default the most decimals of any field.")
+            .requires("csv")
+            .validator(|digits| digits.parse::<u32>().map(|_| ()).map_err(|err| err.to_string()))
+            .takes_value(true))
+        .arg(Arg::with_name("begin")
+            .long("begin")
+            .value_name("program")
+            .help("Run the program before the first line of --each or --csv input.")
+            .takes_value(true))
+        .arg(Arg::with_name("end")
+            .long("end")
+            .value_name("program")
+            .help("Run the program after the last line of --each or --csv input.")
+            .takes_value(true))
+        .arg(Arg::with_name("keep")
+            .long("keep")
+            .help("Keep the stack between the lines of --each input instead of starting with an empty one.")
+            .requires("each"))
+        .arg(Arg::with_name("json")
+            .long("json")
+            .help("Print the final stack, registers and settings, the output of the printing commands and the errors as a JSON document when done.")
+            .conflicts_with("watch"))
+        .arg(Arg::with_name("trace")
+            .long("trace")
+            .help("Log every executed command to stderr with its position, the macro depth and the top of the stack."))
+        .arg(Arg::with_name("trace-registers")
+            .long("trace-registers")
+            .value_name("registers")
+            .help("Only trace commands run inside the macros of these registers, for example ab for registers a and b.")
+            .requires("trace")
+            .takes_value(true))
+        .arg(Arg::with_name("trace-depth")
+            .long("trace-depth")
+            .value_name("depth")
+            .help("Only trace commands up to this macro depth, 0 is the input itself.")
+            .requires("trace")
+            .validator(|depth| depth.parse::<usize>().map(|_| ()).map_err(|err| err.to_string()))
+            .takes_value(true))
+        .arg(Arg::with_name("debug")
+            .long("debug")
+            .help("Stop before the first command of the scripts and read debugger commands from the terminal: step, next, finish, continue, break, watch, stack, regs. Type help at the prompt for the list.")
+            .conflicts_with_all(&["each", "csv", "tui", "watch"]))
+        .arg(Arg::with_name("tui")
+            .long("tui")
+            .help("Run a full-screen stack calculator after the scripts. Enter runs the input or duplicates the top, Backspace drops it, Tab swaps the top two values.")
+            .conflicts_with_all(&["each", "csv", "json", "watch"]))
+        .arg(Arg::with_name("session")
+            .long("session")
+            .value_name("file")
+            .help("Restore the stack, the registers and the settings from this file before the startup file, and save them into it on exit.")
+            .conflicts_with("watch")
+            .takes_value(true))
+        .arg(Arg::with_name("record")
+            .long("record")
+            .value_name("journal")
+            .help("Write every input chunk with its source, the lines read by ? and the printed output into this journal on exit.")
+            .conflicts_with_all(&["each", "csv", "tui", "session", "watch"])
+            .takes_value(true))
+        .arg(Arg::with_name("replay")
+            .long("replay")
+            .value_name("journal")
+            .help("Run a journal written by --record, and check that every input prints the same output as when it was recorded.")
+            .conflicts_with_all(&["record", "each", "csv", "tui", "debug", "session", "json", "watch", "expression", "file", "FILE", "ARGS"])
+            .takes_value(true))
+        .arg(Arg::with_name("watch")
+            .long("watch")
+            .help("Run the scripts with a fresh stack every time one of the script files changes, and print the final stack."))
+        .arg(Arg::with_name("no-rc")
+            .long("no-rc")
+            .help("Do not load the startup file and ignore the DC_ENV_ARGS environment variable."))
+        .arg(Arg::with_name("args-register")
+            .long("args-register")
+            .value_name("register")
+            .help("Store the script arguments in the array of this register instead of pushing them onto the stack, and push only their count.")
+            .validator(|name| if name.chars().count() == 1 {
+                Ok(())
+            } else {
+                Err("register names are a single character".to_owned())
+            })
             .takes_value(true))
         .arg(Arg::with_name("FILE")
-            .help("any files to process one-by-one")
+            .help("any files to process one-by-one, - means the standard input. Without files the standard input is read after the scripts.")
+            .multiple(true)
+            .required(false))
+        .arg(Arg::with_name("ARGS")
+            .help("numbers and [strings] after -- are pushed onto the stack before the scripts run")
             .multiple(true)
+            .last(true)
             .required(false))
-        .get_matches()
+        .get_matches_from(arguments());
+
+    // An argument group of --each and --csv would do, but clap then rejects --each itself.
+    let filter = matches.is_present("each") || matches.is_present("csv");
+    if !filter && (matches.is_present("begin") || matches.is_present("end")) {
+        Error::with_description("--begin and --end need --each or --csv", ErrorKind::MissingRequiredArgument).exit();
+    }
+    matches
+}
+
+/// Returns the command line arguments, with the options in `DC_ENV_ARGS` put in front of the
+/// others unless `--no-rc` is given.
+fn arguments() -> Vec<String> {
+    let mut arguments = split_interpreter_options(env::args().collect());
+
+    let no_rc = arguments.iter().take_while(|argument| *argument != "--").any(|argument| argument == "--no-rc");
+    if let (false, Ok(options)) = (no_rc, env::var("DC_ENV_ARGS")) {
+        for (index, option) in options.split_whitespace().enumerate() {
+            arguments.insert(index + 1, option.to_owned());
+        }
+    }
+
+    arguments
+}
+
+/// Splits up the options of a `#!/usr/bin/dc --dialect=gnu -f` line, which the kernel passes as
+/// one argument in front of the script path. Arguments that merely contain spaces, like
+/// `--expression="2 3 +p"`, are left alone.
+fn split_interpreter_options(mut arguments: Vec<String>) -> Vec<String> {
+    let shebang = match arguments.get(1..3) {
+        Some([options, script]) if options.starts_with('-') && options.contains(char::is_whitespace) => {
+            fs::File::open(script).ok().and_then(|file| io::BufReader::new(file).lines().next()?.ok()).filter(|line| {
+                line.strip_prefix("#!").is_some_and(|interpreter| interpreter.contains(options.as_str()))
+            })
+        }
+        _ => None,
+    };
+
+    if shebang.is_some() {
+        let options = arguments.remove(1);
+        for (index, option) in options.split_whitespace().enumerate() {
+            arguments.insert(index + 1, option.to_owned());
+        }
+    }
+    arguments
 }
 
-fn repl(mut calculator: &mut Calculator) {
-    let stdin = std::io::stdin();
+/// Returns the startup file: `$XDG_CONFIG_HOME/dc/dcrc` (`~/.config/dc/dcrc` by default) or
+/// `~/.dcrc`, whichever exists first.
+fn rc_file() -> Option<PathBuf> {
+    let home = env::var_os("HOME").map(PathBuf::from);
+    let config = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| home.as_ref().map(|home| home.join(".config")));
+
+    vec![
+        config.map(|dir| dir.join("dc").join("dcrc")),
+        home.map(|home| home.join(".dcrc")),
+    ]
+    .into_iter()
+    .flatten()
+    .find(|file| file.is_file())
+}
+
+/// Collects the `-e` and `-f` scripts in the order they were given on the command line.
+fn scripts(matches: &ArgMatches) -> Vec<Script> {
+    let mut scripts = Vec::new();
+
+    if let (Some(indices), Some(values)) = (matches.indices_of("expression"), matches.values_of("expression")) {
+        scripts.extend(indices.zip(values).map(|(index, value)| (index, Script::Expression(value.to_owned()))));
+    }
+    if let (Some(indices), Some(values)) = (matches.indices_of("file"), matches.values_of("file")) {
+        scripts.extend(indices.zip(values).map(|(index, value)| (index, Script::File(value.to_owned()))));
+    }
+
+    scripts.sort_by_key(|(index, _)| *index);
+    scripts.into_iter().map(|(_, script)| script).collect()
+}
+
+/// Makes the script arguments available, either on the stack in the given order or in the
+/// array of `register` starting at index 0, with their count pushed onto the stack. Numbers are
+/// read like `--each` fields, so both `-5` and `_5` are negative.
+fn push_arguments(calculator: &mut Calculator, arguments: Vec<&str>, register: Option<char>) -> Result<(), String> {
+    let values = arguments
+        .iter()
+        .map(|argument| match parse_number(calculator, argument) {
+            Some(num) => Ok(StackValue::Number(num)),
+            None => parse_literal(calculator, argument),
+        })
+        .collect::<Result<Vec<StackValue>, String>>()?;
+    let literals = values
+        .iter()
+        .map(|value| match value {
+            StackValue::Number(num) => Op::Push(num.clone()).to_string(),
+            StackValue::String(string) => Op::PushString(string.clone()).to_string(),
+        })
+        .collect::<Vec<String>>();
+
+    match register {
+        Some(register) => {
+            let count = values.len();
+            for (index, value) in values.into_iter().enumerate() {
+                calculator.set_array_value(register, index, value);
+            }
+            calculator.push(StackValue::Number(BigInt::from(count)))?;
+
+            let stores = literals.iter().enumerate().map(|(index, literal)| format!("{} {}:{}", literal, index, register));
+            let source = stores.chain(std::iter::once(count.to_string())).collect::<Vec<String>>();
+            calculator.record_entry(Entry::Input(Source::Arguments, source.join(" ")));
+        }
+        None => {
+            for value in values {
+                calculator.push(value)?;
+            }
+            calculator.record_entry(Entry::Input(Source::Arguments, literals.join(" ")));
+        }
+    }
+
+    Ok(())
+}
+
+/// Executes the given file, or the standard input if the name is `-`.
+fn process_file(calculator: &mut Calculator, file: &str) -> OpResult {
+    if file == "-" {
+        return repl(calculator);
+    }
+
+    match fs::read_to_string(file) {
+        Ok(content) => {
+            calculator.record_entry(Entry::Input(Source::File(file.to_owned()), content.clone()));
+            checked(process_script(calculator, file, &content))
+        }
+        Err(err) => {
+            eprintln!("dc: Could not open file {}: {}", file, err);
+            OpResult::Ok
+        }
+    }
+}
+
+/// Reads commands from the standard input until it ends or a command quits. A terminal gets a line
+/// editor with history, piped input is streamed line by line. Lines ending inside a string are
+/// joined with the following ones, and run once the string is closed.
+fn repl(calculator: &mut Calculator) -> OpResult {
+    if io::stdin().is_terminal() {
+        let config = Config::builder().completion_type(CompletionType::List).build();
+        if let Ok(mut editor) = Editor::with_config(config) {
+            editor.set_helper(Some(DcHelper::default()));
+            return interactive(calculator, editor);
+        }
+    }
+
+    let stdin = io::stdin();
     let mut buffer = String::new();
+    let mut pending = String::new();
 
     loop {
         buffer.clear();
-        stdin.read_line(&mut buffer).unwrap();
+        match stdin.read_line(&mut buffer) {
+            Ok(0) => return process_line(calculator, &pending).unwrap_or(OpResult::Ok),
+            Ok(_) => (),
+            Err(err) => {
+                eprintln!("dc: could not read input: {}", err);
+                std::process::exit(1);
+            }
+        }
 
-        match process_input(&mut calculator, buffer.trim().as_ref()) {
-            Ok(result) => match result {
-                OpResult::Exit => break,
-                _ => (),
-            },
+        pending.push_str(&buffer);
+        if is_unterminated(calculator, &pending) {
+            continue;
+        }
+
+        if let Some(result) = process_line(calculator, &std::mem::take(&mut pending)) {
+            return result;
+        }
+    }
+}
+
+fn interactive(calculator: &mut Calculator, mut editor: Editor<DcHelper, DefaultHistory>) -> OpResult {
+    let history = history_file();
+    if let Some(file) = &history {
+        let _ = editor.load_history(file);
+    }
+
+    let mut pending = String::new();
+    let result = loop {
+        let prompt = if pending.is_empty() { "" } else { "... " };
+        if let Some(helper) = editor.helper_mut() {
+            helper.update(calculator, &pending);
+        }
+        match editor.readline(prompt) {
+            Ok(line) => {
+                pending.push_str(&line);
+                pending.push('\n');
+                if is_unterminated(calculator, &pending) {
+                    continue;
+                }
+
+                let input = std::mem::take(&mut pending);
+                if !input.trim().is_empty() {
+                    let _ = editor.add_history_entry(input.trim_end());
+                }
+                if let Some(result) = process_line(calculator, &input) {
+                    break result;
+                }
+            }
+            Err(ReadlineError::Interrupted) => pending.clear(),
+            Err(ReadlineError::Eof) => break process_line(calculator, &pending).unwrap_or(OpResult::Ok),
             Err(err) => {
-                println!("Error: {}", err);
-                break;
+                eprintln!("dc: {}", err);
+                break OpResult::Ok;
+            }
+        }
+    };
+
+    if let Some(file) = &history {
+        let saved = file
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| editor.save_history(file).map_err(io::Error::other));
+        if let Err(err) = saved {
+            eprintln!("dc: Could not save history to {}: {}", file.display(), err);
+        }
+    }
+
+    result
+}
+
+/// Runs one unit of input, returns the result if the REPL has to stop.
+fn process_line(calculator: &mut Calculator, line: &str) -> Option<OpResult> {
+    if let Some(command) = meta::parse(line) {
+        return match command {
+            Ok(command) => run_command(calculator, command),
+            Err(err) => {
+                eprintln!("dc: {}", err);
+                None
             }
         };
     }
+
+    if line.trim().is_empty() {
+        return None;
+    }
+
+    calculator.checkpoint();
+    calculator.record_entry(Entry::Input(Source::Repl, line.trim().to_owned()));
+    match checked(process_input(calculator, line.trim())) {
+        OpResult::Exit => Some(OpResult::Exit),
+        _ => None,
+    }
+}
+
+/// Returns the result of running some input, or stops dc with the error that ended the run.
+fn checked(result: Result<OpResult, String>) -> OpResult {
+    result.unwrap_or_else(|err| {
+        eprintln!("dc: {}", err);
+        std::process::exit(1)
+    })
+}
+
+/// Runs a REPL colon command, returns the result if the REPL has to stop.
+fn run_command(calculator: &mut Calculator, command: Command) -> Option<OpResult> {
+    let listing = match command {
+        Command::Stack => meta::stack(calculator),
+        Command::Registers => meta::registers(calculator),
+        Command::Radix => meta::radix(calculator),
+        Command::Help(topic) => match meta::help(topic.as_deref()) {
+            Ok(help) => help,
+            Err(err) => {
+                eprintln!("dc: {}", err);
+                return None;
+            }
+        },
+        Command::Load(file) => {
+            calculator.checkpoint();
+            return match process_file(calculator, &file) {
+                OpResult::Exit => Some(OpResult::Exit),
+                _ => None,
+            };
+        }
+        Command::Save(file) => {
+            if let Err(err) = meta::to_source(calculator).and_then(|source| fs::write(&file, source).map_err(|err| err.to_string())) {
+                eprintln!("dc: Could not save to {}: {}", file, err);
+            }
+            return None;
+        }
+        Command::Reset => {
+            calculator.record_entry(Entry::Command(":reset".to_owned()));
+            calculator.checkpoint();
+            calculator.reset();
+            return None;
+        }
+        Command::Undo => {
+            calculator.record_entry(Entry::Command(":undo".to_owned()));
+            if !calculator.undo() {
+                eprintln!("dc: nothing to undo");
+            }
+            return None;
+        }
+        Command::Redo => {
+            calculator.record_entry(Entry::Command(":redo".to_owned()));
+            if !calculator.redo() {
+                eprintln!("dc: nothing to redo");
+            }
+            return None;
+        }
+    };
+
+    print_stdout(&listing);
+    None
+}
+
+/// `$XDG_STATE_HOME/dc/history`, or `~/.local/state/dc/history`.
+fn history_file() -> Option<PathBuf> {
+    env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("state")))
+        .map(|dir| dir.join("dc").join("history"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(arguments: &[&str]) -> Vec<String> {
+        arguments.iter().map(|argument| argument.to_string()).collect()
+    }
+
+    #[test]
+    fn interpreter_options() {
+        let script = env::temp_dir().join(format!("dc-shebang-{}.dc", std::process::id()));
+        fs::write(&script, "#!/usr/bin/dc --dialect=gnu -f\n+pq\n").unwrap();
+        let path = script.to_str().unwrap();
+
+        let split = split_interpreter_options(strings(&["dc", "--dialect=gnu -f", path, "--", "2"]));
+        assert_eq!(split, strings(&["dc", "--dialect=gnu", "-f", path, "--", "2"]));
+
+        let other = split_interpreter_options(strings(&["dc", "--dialect=posix -f", path]));
+        assert_eq!(other, strings(&["dc", "--dialect=posix -f", path]));
+        fs::remove_file(script).unwrap();
+    }
+
+    #[test]
+    fn negative_arguments() {
+        let mut calculator = Calculator::new();
+        push_arguments(&mut calculator, vec!["-5", "_6", "[-7]"], None).unwrap();
+        let values = calculator.iter().map(|value| value.literal()).collect::<Vec<String>>();
+        assert_eq!(values, ["-5", "-6", "[-7]"]);
+        assert!(push_arguments(&mut calculator, vec!["-x"], None).is_err());
+    }
+
+    #[test]
+    fn spaces_in_options() {
+        let arguments = strings(&["dc", "--expression=a b"]);
+        assert_eq!(split_interpreter_options(arguments.clone()), arguments);
+
+        let arguments = strings(&["dc", "--expression=10 2 * 1 + 2 *p", "--no-rc"]);
+        assert_eq!(split_interpreter_options(arguments.clone()), arguments);
+    }
 }
@@ -0,0 +1,289 @@
+use crate::calculator::{Calculator, StackValue};
+
+/// A REPL command starting with a colon, which is not passed to the calculator.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Stack,
+    Registers,
+    Load(String),
+    Save(String),
+    Reset,
+    Undo,
+    Redo,
+    Radix,
+    Help(Option<String>),
+}
+
+const COMMANDS: &[(&str, &str)] = &[
+    (":stack", "show the stack with indices and types, the top is 1"),
+    (":regs", "show the registers and arrays holding values"),
+    (":load file", "run a dc script"),
+    (":save file", "write a dc script rebuilding the stack, the registers and the settings"),
+    (":reset", "empty the stack and the registers, restore the default settings"),
+    (":undo", "return to the state before the last line that changed it"),
+    (":redo", "revert the last undo"),
+    (":radix", "show the input and output radix and the precision"),
+    (":help [command]", "list the commands, or describe one"),
+];
+
+const OPERATIONS: &[(&str, &str)] = &[
+    ("p", "print the top of the stack"),
+    ("n", "pop and print the top of the stack without a newline"),
+    ("f", "print the whole stack"),
+    ("+", "add the top two numbers"),
+    ("-", "subtract the top number from the one below it"),
+    ("*", "multiply the top two numbers"),
+    ("/", "divide the second number by the top one"),
+    ("%", "remainder of dividing the second number by the top one"),
+    ("~", "push both the quotient and the remainder of a division"),
+    ("^", "raise the second number to the power of the top one"),
+    ("v", "square root of the top number"),
+    ("|", "modular exponentiation: base, exponent and modulus"),
+    ("c", "clear the stack"),
+    ("d", "duplicate the top of the stack"),
+    ("r", "swap the top two values"),
+    ("R", "rotate the top n values"),
+    ("t", "drop the top of the stack, in the extended dialect"),
+    ("z", "push the depth of the stack"),
+    ("i", "pop the input radix"),
+    ("o", "pop the output radix"),
+    ("k", "pop the precision"),
+    ("I", "push the input radix"),
+    ("O", "push the output radix"),
+    ("K", "push the precision"),
+    ("[text]", "push a string"),
+    ("sr", "pop the top of the stack into register r"),
+    ("lr", "push a copy of register r"),
+    ("Sr", "push the top of the stack onto the stack of register r"),
+    ("Lr", "pop the stack of register r onto the stack"),
+    (":r", "store the second value at the index on top in the array of register r"),
+    (";r", "push the value at the index on top from the array of register r"),
+    ("x", "run the string on top of the stack as a macro"),
+    ("?", "read a line of input and run it"),
+    ("<r", "run register r if the top number is less than the one below it"),
+    (">r", "run register r if the top number is greater than the one below it"),
+    ("=r", "run register r if the top two numbers are equal"),
+    ("!<r", "run register r if the top number is not less than the one below it"),
+    ("!>r", "run register r if the top number is not greater than the one below it"),
+    ("!=r", "run register r if the top two numbers are not equal"),
+    ("!command", "run the rest of the line as a shell command, in the posix and gnu dialects"),
+    ("#", "comment until the end of the line"),
+    ("q", "quit"),
+];
+
+/// Recognizes a REPL command. Returns `None` for ordinary dc input, such as `:a` storing into an
+/// array, and an error for a command with wrong arguments.
+pub fn parse(line: &str) -> Option<Result<Command, String>> {
+    let line = line.trim();
+    if !line.starts_with(':') {
+        return None;
+    }
+
+    let mut words = line[1..].splitn(2, char::is_whitespace);
+    let name = words.next().unwrap_or("");
+    let argument = words.next().map(str::trim).filter(|argument| !argument.is_empty());
+    let command = match (name, argument) {
+        ("stack", None) => Command::Stack,
+        ("regs", None) => Command::Registers,
+        ("load", Some(file)) => Command::Load(file.to_owned()),
+        ("save", Some(file)) => Command::Save(file.to_owned()),
+        ("reset", None) => Command::Reset,
+        ("undo", None) => Command::Undo,
+        ("redo", None) => Command::Redo,
+        ("radix", None) => Command::Radix,
+        ("help", topic) => Command::Help(topic.map(str::to_owned)),
+        ("stack", _) | ("regs", _) | ("reset", _) | ("undo", _) | ("redo", _) | ("radix", _) | ("load", _) | ("save", _) => {
+            let usage = COMMANDS.iter().find(|(usage, _)| usage[1..].starts_with(name)).unwrap().0;
+            return Some(Err(format!("usage: {}", usage)));
+        }
+        _ => return None,
+    };
+
+    Some(Ok(command))
+}
+
+/// Lists the stack from the bottom, with the index counted from the top and the type of each value.
+pub fn stack(calculator: &Calculator) -> String {
+    let depth = calculator.iter().count();
+    if depth == 0 {
+        return "stack empty\n".to_owned();
+    }
+
+    calculator
+        .iter()
+        .enumerate()
+        .map(|(index, value)| format!("{:>4}: {:<6} {}\n", depth - index, value.type_name(), value.literal()))
+        .collect()
+}
+
+/// Lists the stacks and arrays of the registers holding values.
+pub fn registers(calculator: &Calculator) -> String {
+    let mut listing = String::new();
+    for name in calculator.register_names() {
+        let stack = calculator.register_stack(name);
+        if !stack.is_empty() {
+            let values = stack.into_iter().map(StackValue::literal).collect::<Vec<String>>();
+            listing.push_str(&format!("{}: {}\n", name, values.join(" ")));
+        }
+
+        let array = calculator.register_array(name);
+        if !array.is_empty() {
+            let elements = array
+                .into_iter()
+                .map(|(index, value)| format!("{}={}", index, value.literal()))
+                .collect::<Vec<String>>();
+            listing.push_str(&format!("{}[]: {}\n", name, elements.join(" ")));
+        }
+    }
+
+    if listing.is_empty() {
+        "no registers in use\n".to_owned()
+    } else {
+        listing
+    }
+}
+
+pub fn radix(calculator: &Calculator) -> String {
+    format!(
+        "input radix {}, output radix {}, precision {}\n",
+        calculator.input_radix(),
+        calculator.output_radix(),
+        calculator.precision()
+    )
+}
+
+/// Describes the REPL commands and the dc operations, or the ones starting with `topic`.
+pub fn help(topic: Option<&str>) -> Result<String, String> {
+    let entries = match topic {
+        None => COMMANDS.iter().chain(OPERATIONS).collect::<Vec<_>>(),
+        Some(topic) => {
+            let colon = format!(":{}", topic.trim_start_matches(':'));
+            let matching = OPERATIONS
+                .iter()
+                .filter(|(usage, _)| usage.starts_with(topic))
+                .chain(COMMANDS.iter().filter(|(usage, _)| topic.len() > 1 && usage.starts_with(&colon)))
+                .collect::<Vec<_>>();
+            if matching.is_empty() {
+                return Err(format!("no help for {}", topic));
+            }
+            matching
+        }
+    };
+
+    Ok(entries.into_iter().map(|(usage, description)| format!("{:<16} {}\n", usage, description)).collect())
+}
+
+/// Writes dc source that rebuilds the stack, the registers and the settings of the calculator.
+/// Strings with unbalanced brackets cannot be written as dc source.
+pub fn to_source(calculator: &Calculator) -> Result<String, String> {
+    let mut source = String::new();
+    for name in calculator.register_names() {
+        for value in calculator.register_stack(name) {
+            source.push_str(&format!("{} S{}\n", source_literal(value)?, name));
+        }
+        for (index, value) in calculator.register_array(name) {
+            source.push_str(&format!("{} {}:{}\n", source_literal(value)?, index, name));
+        }
+    }
+
+    let stack = calculator.iter().map(source_literal).collect::<Result<Vec<String>, String>>()?;
+    if !stack.is_empty() {
+        source.push_str(&format!("{}\n", stack.join(" ")));
+    }
+    source.push_str(&format!(
+        "{}k {}o {}i\n",
+        calculator.precision(),
+        calculator.output_radix(),
+        calculator.input_radix()
+    ));
+
+    Ok(source)
+}
+
+fn source_literal(value: &StackValue) -> Result<String, String> {
+    match value {
+        StackValue::Number(num) => Ok(num.to_string().replace('-', "_")),
+        StackValue::String(string) => {
+            let mut depth = 0;
+            for ch in string.chars() {
+                match ch {
+                    '[' => depth += 1,
+                    ']' if depth == 0 => return Err(format!("cannot save string with unbalanced brackets: {}", string)),
+                    ']' => depth -= 1,
+                    _ => (),
+                }
+            }
+            if depth > 0 {
+                return Err(format!("cannot save string with unbalanced brackets: {}", string));
+            }
+            Ok(format!("[{}]", string))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::calculator::Calculator;
+    use crate::meta::*;
+    use crate::process_input;
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(parse(":stack"), Some(Ok(Command::Stack)));
+        assert_eq!(parse(" :load  lib.dc "), Some(Ok(Command::Load("lib.dc".to_owned()))));
+        assert_eq!(parse(":help"), Some(Ok(Command::Help(None))));
+        assert_eq!(parse(":undo"), Some(Ok(Command::Undo)));
+        assert_eq!(parse(":help ;"), Some(Ok(Command::Help(Some(";".to_owned())))));
+        assert_eq!(parse(":save"), Some(Err("usage: :save file".to_owned())));
+        assert_eq!(parse(":stack 1"), Some(Err("usage: :stack".to_owned())));
+    }
+
+    #[test]
+    fn parse_dc_input() {
+        assert_eq!(parse("1 0:a"), None);
+        assert_eq!(parse(":a"), None);
+        assert_eq!(parse(":s 1"), None);
+    }
+
+    #[test]
+    fn listings() {
+        let mut calculator = Calculator::new();
+        assert_eq!(stack(&calculator), "stack empty\n");
+        assert_eq!(registers(&calculator), "no registers in use\n");
+
+        process_input(&mut calculator, "[d*]sq 5 0:q 6 1:q _1 [abc]").unwrap();
+        assert_eq!(stack(&calculator), "   2: number -1\n   1: string [abc]\n");
+        assert_eq!(registers(&calculator), "q: [d*]\nq[]: 0=5 1=6\n");
+        assert_eq!(radix(&calculator), "input radix 10, output radix 10, precision 0\n");
+    }
+
+    #[test]
+    fn help_topics() {
+        assert_eq!(help(Some("d")), Ok("d                duplicate the top of the stack\n".to_owned()));
+        assert!(help(Some("!")).unwrap().lines().count() == 4);
+        assert!(help(Some("reset")).unwrap().starts_with(":reset"));
+        assert_eq!(help(Some("y")), Err("no help for y".to_owned()));
+        assert!(help(None).unwrap().lines().count() > 40);
+    }
+
+    #[test]
+    fn source_round_trip() {
+        let mut calculator = Calculator::new();
+        process_input(&mut calculator, "[d*]sq 1Sq 5 0:q _1 [a [b]] 2k").unwrap();
+        let source = to_source(&calculator).unwrap();
+
+        let mut restored = Calculator::new();
+        process_input(&mut restored, &source).unwrap();
+        assert_eq!(to_source(&restored), Ok(source));
+        assert_eq!(stack(&restored), stack(&calculator));
+        assert_eq!(registers(&restored), registers(&calculator));
+        assert_eq!(restored.precision(), 2);
+    }
+
+    #[test]
+    fn source_unbalanced_string() {
+        let mut calculator = Calculator::new();
+        calculator.push(StackValue::String("a]".to_owned())).unwrap();
+        assert_eq!(to_source(&calculator), Err("cannot save string with unbalanced brackets: a]".to_owned()));
+    }
+}
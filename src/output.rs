@@ -0,0 +1,198 @@
+use crate::calculator::Diagnostic;
+use std::any::Any;
+use std::io::{self, Write};
+
+/// Where a calculator writes the text of the printing commands and its error reports, set with
+/// `Calculator::set_output` and retrieved with `Calculator::output_as`.
+pub trait Output: Any {
+    /// Writes text printed by `p`, `n` or `f`. A failure ends the run.
+    fn print(&mut self, text: &str) -> io::Result<()>;
+
+    /// Writes an error that occurred while running some input.
+    fn report(&mut self, diagnostic: &Diagnostic) -> io::Result<()>;
+
+    /// Writes a line logged by `--trace`, see `Calculator::set_trace`. Goes to stderr unless
+    /// overridden.
+    fn trace(&mut self, line: &str) -> io::Result<()> {
+        writeln!(io::stderr(), "{}", line)
+    }
+}
+
+/// Prints to stdout, flushing after every command so prompts show up, and reports to stderr.
+#[derive(Debug, Default)]
+pub struct Stdout;
+
+impl Output for Stdout {
+    fn print(&mut self, text: &str) -> io::Result<()> {
+        let mut stdout = io::stdout();
+        stdout.write_all(text.as_bytes())?;
+        stdout.flush()
+    }
+
+    fn report(&mut self, diagnostic: &Diagnostic) -> io::Result<()> {
+        writeln!(io::stderr(), "dc: {}", diagnostic)
+    }
+}
+
+/// Keeps the printed text, one string per command, and the errors in memory. Traces still go to
+/// stderr.
+#[derive(Debug, Default)]
+pub struct Buffer {
+    printed: Vec<String>,
+    reported: Vec<Diagnostic>,
+}
+
+impl Buffer {
+    pub fn printed(&self) -> &[String] {
+        &self.printed
+    }
+
+    pub fn reported(&self) -> &[Diagnostic] {
+        &self.reported
+    }
+}
+
+impl Output for Buffer {
+    fn print(&mut self, text: &str) -> io::Result<()> {
+        self.printed.push(text.to_owned());
+        Ok(())
+    }
+
+    fn report(&mut self, diagnostic: &Diagnostic) -> io::Result<()> {
+        self.reported.push(diagnostic.clone());
+        Ok(())
+    }
+}
+
+/// Writes the printed text, the errors as `dc: ` lines and the traces to any writer such as a
+/// file or a socket.
+#[derive(Debug)]
+pub struct Writer<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn new(writer: W) -> Writer<W> {
+        Writer { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write + 'static> Output for Writer<W> {
+    fn print(&mut self, text: &str) -> io::Result<()> {
+        self.writer.write_all(text.as_bytes())?;
+        self.writer.flush()
+    }
+
+    fn report(&mut self, diagnostic: &Diagnostic) -> io::Result<()> {
+        writeln!(self.writer, "dc: {}", diagnostic)?;
+        self.writer.flush()
+    }
+
+    fn trace(&mut self, line: &str) -> io::Result<()> {
+        writeln!(self.writer, "{}", line)?;
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::calculator::{Calculator, Trace};
+    use crate::output::*;
+    use crate::process_input;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// A writer the test can still read after giving it to the calculator.
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Fails like stdout piped into a command that already exited.
+    struct BrokenPipe;
+
+    impl Write for BrokenPipe {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::from(io::ErrorKind::BrokenPipe))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn writer() {
+        let written = Shared::default();
+        let mut calculator = Calculator::new();
+        calculator.set_output(Box::new(Writer::new(written.clone())));
+        process_input(&mut calculator, "1p 2n 0/").unwrap();
+        assert_eq!(String::from_utf8(written.0.borrow().clone()).unwrap(), "1\n2dc: division by zero\n");
+        assert!(calculator.output().is_empty());
+    }
+
+    #[test]
+    fn writer_trace() {
+        let written = Shared::default();
+        let mut calculator = Calculator::new();
+        calculator.set_output(Box::new(Writer::new(written.clone())));
+        calculator.set_trace(Some(Trace::default()));
+        process_input(&mut calculator, "2p").unwrap();
+        assert_eq!(
+            String::from_utf8(written.0.borrow().clone()).unwrap(),
+            "trace 1:1 depth=0 macro=- op=2 stack=[2]\n2\ntrace 1:2 depth=0 macro=- op=p stack=[2]\n"
+        );
+    }
+
+    #[test]
+    fn buffer() {
+        let mut calculator = Calculator::new();
+        calculator.set_output(Box::new(Buffer::default()));
+        process_input(&mut calculator, "1 2f r").unwrap();
+        assert_eq!(calculator.output(), ["1\n2\n"]);
+        assert!(calculator.errors().is_empty());
+    }
+
+    #[test]
+    fn write_errors() {
+        let mut calculator = Calculator::new();
+        calculator.set_output(Box::new(Writer::new(BrokenPipe)));
+        assert!(process_input(&mut calculator, "1n 2").unwrap_err().starts_with("could not write output: "));
+        assert_eq!(calculator.iter().count(), 1);
+        assert!(calculator.print("1".to_owned()).unwrap_err().starts_with("could not write output: "));
+    }
+
+    #[test]
+    fn write_errors_end_loops() {
+        let mut calculator = Calculator::new();
+        calculator.set_output(Box::new(Writer::new(BrokenPipe)));
+        assert!(process_input(&mut calculator, "0[1+dplax]salax").is_err());
+        assert_eq!(calculator.peek().map(|value| value.to_string()), Some("1".to_owned()));
+    }
+
+    #[test]
+    fn output_as() {
+        let mut calculator = Calculator::new();
+        assert!(calculator.output_as::<Stdout>().is_some());
+        calculator.set_output(Box::new(Writer::new(Vec::new())));
+        process_input(&mut calculator, "1p").unwrap();
+        assert_eq!(calculator.output_as::<Writer<Vec<u8>>>().unwrap().get_ref(), b"1\n");
+        assert!(calculator.output_as::<Buffer>().is_none());
+    }
+}
@@ -1,6 +1,11 @@
+use crate::calculator::Dialect;
 use num_bigint::BigInt;
+use std::fmt;
+use std::ops::Range;
 
+/// A dc command. `Display` writes it back as dc source.
 #[derive(Debug, PartialEq, Clone)]
+#[non_exhaustive]
 pub enum Op {
     Add,
     Sub,
@@ -21,58 +26,320 @@ pub enum Op {
     Clear,
     Duplicate,
     Reverse,
+    Rotate,
+    /// Discards the top of the stack, `t` in the extended dialect.
+    Drop,
     SetInputRadix,
     SetOutputRadix,
     SetPrecision,
     GetInputRadix,
     GetOutputRadix,
     GetPrecision,
+
+    // Strings, registers and macros
+    PushString(String),
+    Store(char),
+    Load(char),
+    PushRegister(char),
+    PopRegister(char),
+    StoreArray(char),
+    LoadArray(char),
+    StackDepth,
+    Execute,
+    /// Reads a line of input and runs it.
+    Read,
+    /// Runs the rest of the line as a shell command, `!` in the posix and gnu dialects.
+    Shell(String),
+    Conditional(Comparison, char),
+
+    /// A character that is not a known command, reported when it is executed.
+    Unimplemented(char),
+    /// A command that exists, but is not part of the selected dialect.
+    Unsupported(char),
+}
+
+/// The condition of a conditional command such as `<a` or `!=a`.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Comparison {
+    Less,
+    Greater,
+    Equal,
+    NotLess,
+    NotGreater,
+    NotEqual,
+}
+
+/// `Shell` has to be followed by a line break.
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Op::Add => write!(f, "+"),
+            Op::Sub => write!(f, "-"),
+            Op::Mul => write!(f, "*"),
+            Op::Div => write!(f, "/"),
+            Op::Mod => write!(f, "%"),
+            Op::DivRem => write!(f, "~"),
+            Op::Exp => write!(f, "^"),
+            Op::Sqrt => write!(f, "v"),
+            Op::ModExp => write!(f, "|"),
+            Op::PrintPop => write!(f, "n"),
+            Op::PrintPeek => write!(f, "p"),
+            Op::PrintAll => write!(f, "f"),
+            Op::Push(num) => write!(f, "{}", num.to_string().replace('-', "_")),
+            Op::Exit => write!(f, "q"),
+            Op::Clear => write!(f, "c"),
+            Op::Duplicate => write!(f, "d"),
+            Op::Reverse => write!(f, "r"),
+            Op::Rotate => write!(f, "R"),
+            Op::Drop => write!(f, "t"),
+            Op::SetInputRadix => write!(f, "i"),
+            Op::SetOutputRadix => write!(f, "o"),
+            Op::SetPrecision => write!(f, "k"),
+            Op::GetInputRadix => write!(f, "I"),
+            Op::GetOutputRadix => write!(f, "O"),
+            Op::GetPrecision => write!(f, "K"),
+            Op::PushString(string) => write!(f, "[{}]", string),
+            Op::Store(register) => write!(f, "s{}", register),
+            Op::Load(register) => write!(f, "l{}", register),
+            Op::PushRegister(register) => write!(f, "S{}", register),
+            Op::PopRegister(register) => write!(f, "L{}", register),
+            Op::StoreArray(register) => write!(f, ":{}", register),
+            Op::LoadArray(register) => write!(f, ";{}", register),
+            Op::StackDepth => write!(f, "z"),
+            Op::Execute => write!(f, "x"),
+            Op::Read => write!(f, "?"),
+            Op::Shell(command) => write!(f, "!{}", command),
+            Op::Conditional(comparison, register) => {
+                let command = match comparison {
+                    Comparison::Less => "<",
+                    Comparison::Greater => ">",
+                    Comparison::Equal => "=",
+                    Comparison::NotLess => "!<",
+                    Comparison::NotGreater => "!>",
+                    Comparison::NotEqual => "!=",
+                };
+                write!(f, "{}{}", command, register)
+            }
+            Op::Unimplemented(ch) | Op::Unsupported(ch) => write!(f, "{}", ch),
+        }
+    }
+}
+
+/// The place of a command in its source, both counted from 1.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Position { line: 1, column: 1 }
+    }
 }
 
-pub fn tokenize(str: &str) -> Result<Vec<Op>, String> {
+/// An operation parsed from dc source, with its place in the source.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Token {
+    pub op: Op,
+    pub position: Position,
+    /// The bytes of the source the command was read from.
+    pub span: Range<usize>,
+}
+
+/// A tokenizing failure, with the place of the offending command.
+#[derive(Debug, PartialEq, Clone)]
+pub struct LexError {
+    pub kind: LexErrorKind,
+    pub position: Position,
+    /// The bytes from the offending command to the end of the source.
+    pub span: Range<usize>,
+    pub message: String,
+}
+
+/// What made the tokenizing fail. The first two mean the source is only incomplete, more input can
+/// still make it valid.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[non_exhaustive]
+pub enum LexErrorKind {
+    UnterminatedString,
+    MissingRegister,
+    InvalidNumber,
+}
+
+pub fn tokenize(str: &str, dialect: Dialect) -> Result<Vec<Op>, String> {
+    lex(str, dialect)
+        .map(|tokens| tokens.into_iter().map(|token| token.op).collect())
+        .map_err(|err| err.message)
+}
+
+/// Tokenizes the input keeping the position of every command. On failure the error has the
+/// position of the offending command.
+pub fn lex(str: &str, dialect: Dialect) -> Result<Vec<Token>, LexError> {
+    match lex_partial(str, dialect) {
+        (tokens, None) => Ok(tokens),
+        (_, Some(err)) => Err(err),
+    }
+}
+
+/// Same as `lex`, but also returns the commands before an error, for highlighting input that is
+/// still being typed.
+pub fn lex_partial(str: &str, dialect: Dialect) -> (Vec<Token>, Option<LexError>) {
     let mut tokens = Vec::new();
-    let length = str.len();
-    let buffer = str.to_owned();
+    let mut position = Position::default();
+    match scan(str, dialect, &mut tokens, &mut position) {
+        Ok(()) => (tokens, None),
+        Err((kind, message)) => {
+            let start = str
+                .split_inclusive('\n')
+                .take(position.line - 1)
+                .map(str::len)
+                .sum::<usize>();
+            let offset = str[start..].char_indices().nth(position.column - 1).map_or(str.len(), |(offset, _)| start + offset);
+            (tokens, Some(LexError { kind, position, span: offset..str.len(), message }))
+        }
+    }
+}
+
+fn scan(
+    str: &str,
+    dialect: Dialect,
+    tokens: &mut Vec<Token>,
+    position: &mut Position,
+) -> Result<(), (LexErrorKind, String)> {
+    let mut ops = Vec::new();
+    let buffer = str.chars().collect::<Vec<char>>();
+    let offsets = str.char_indices().map(|(offset, _)| offset).chain(Some(str.len())).collect::<Vec<usize>>();
+    let length = buffer.len();
     let mut index = 0;
+    let mut scanned = 0;
 
     while index < length {
-        let ch = buffer.chars().nth(index).unwrap();
+        let span = offsets[scanned]..offsets[index];
+        tokens.extend(ops.drain(..).map(|op| Token { op, position: *position, span: span.clone() }));
+        for &skipped in &buffer[scanned..index] {
+            if skipped == '\n' {
+                position.line += 1;
+                position.column = 1;
+            } else {
+                position.column += 1;
+            }
+        }
+        scanned = index;
+
+        let ch = buffer[index];
         index += 1;
 
+        if !dialect.accepts(ch) {
+            ops.push(Op::Unsupported(ch));
+            continue;
+        }
+
         match ch {
+            ' ' | '\t' | '\r' | '\n' => (),
+            '#' => {
+                while index < length && buffer[index] != '\n' {
+                    index += 1;
+                }
+            },
+
             // Stack operations
-            'c' => tokens.push(Op::Clear),
-            'd' => tokens.push(Op::Duplicate),
-            'r' => tokens.push(Op::Reverse),
+            'c' => ops.push(Op::Clear),
+            'd' => ops.push(Op::Duplicate),
+            'r' => ops.push(Op::Reverse),
+            'R' => ops.push(Op::Rotate),
+            't' => ops.push(Op::Drop),
 
-            'i' => tokens.push(Op::SetInputRadix),
-            'o' => tokens.push(Op::SetOutputRadix),
-            'k' => tokens.push(Op::SetPrecision),
-            'I' => tokens.push(Op::GetInputRadix),
-            'O' => tokens.push(Op::GetOutputRadix),
-            'K' => tokens.push(Op::GetPrecision),
+            'i' => ops.push(Op::SetInputRadix),
+            'o' => ops.push(Op::SetOutputRadix),
+            'k' => ops.push(Op::SetPrecision),
+            'I' => ops.push(Op::GetInputRadix),
+            'O' => ops.push(Op::GetOutputRadix),
+            'K' => ops.push(Op::GetPrecision),
 
             // Calculator operations
-            'q' => tokens.push(Op::Exit),
-            'p' => tokens.push(Op::PrintPeek),
-            'n' => tokens.push(Op::PrintPop),
-            'f' => tokens.push(Op::PrintAll),
-            '+' => tokens.push(Op::Add),
-            '-' => tokens.push(Op::Sub),
-            '*' => tokens.push(Op::Mul),
-            '/' => tokens.push(Op::Div),
-            '%' => tokens.push(Op::Mod),
-            '~' => tokens.push(Op::DivRem),
-            '^' => tokens.push(Op::Exp),
-            'v' => tokens.push(Op::Sqrt),
-            '|' => tokens.push(Op::ModExp),
+            'q' => ops.push(Op::Exit),
+            'p' => ops.push(Op::PrintPeek),
+            'n' => ops.push(Op::PrintPop),
+            'f' => ops.push(Op::PrintAll),
+            '+' => ops.push(Op::Add),
+            '-' => ops.push(Op::Sub),
+            '*' => ops.push(Op::Mul),
+            '/' => ops.push(Op::Div),
+            '%' => ops.push(Op::Mod),
+            '~' => ops.push(Op::DivRem),
+            '^' => ops.push(Op::Exp),
+            'v' => ops.push(Op::Sqrt),
+            '|' => ops.push(Op::ModExp),
+
+            // Strings, registers and macros
+            '[' => {
+                let mut string = String::new();
+                let mut depth = 1;
+                while index < length {
+                    let next = buffer[index];
+                    index += 1;
+                    match next {
+                        '[' => depth += 1,
+                        ']' => depth -= 1,
+                        _ => (),
+                    };
+                    if depth == 0 {
+                        break;
+                    }
+                    string.push(next);
+                }
+
+                if depth > 0 {
+                    return Err((LexErrorKind::UnterminatedString, format!("Unterminated string: [{}", string)));
+                }
+                ops.push(Op::PushString(string));
+            },
+            's' => ops.push(Op::Store(register(&buffer, &mut index, ch)?)),
+            'l' => ops.push(Op::Load(register(&buffer, &mut index, ch)?)),
+            'S' => ops.push(Op::PushRegister(register(&buffer, &mut index, ch)?)),
+            'L' => ops.push(Op::PopRegister(register(&buffer, &mut index, ch)?)),
+            ':' => ops.push(Op::StoreArray(register(&buffer, &mut index, ch)?)),
+            ';' => ops.push(Op::LoadArray(register(&buffer, &mut index, ch)?)),
+            'z' => ops.push(Op::StackDepth),
+            'x' => ops.push(Op::Execute),
+            '?' => ops.push(Op::Read),
+            '<' => ops.push(Op::Conditional(Comparison::Less, register(&buffer, &mut index, ch)?)),
+            '>' => ops.push(Op::Conditional(Comparison::Greater, register(&buffer, &mut index, ch)?)),
+            '=' => ops.push(Op::Conditional(Comparison::Equal, register(&buffer, &mut index, ch)?)),
+            '!' => {
+                let comparison = match buffer.get(index) {
+                    Some('<') => Comparison::NotLess,
+                    Some('>') => Comparison::NotGreater,
+                    Some('=') => Comparison::NotEqual,
+                    _ if dialect == Dialect::Extended => {
+                        ops.push(Op::Unimplemented(ch));
+                        continue;
+                    }
+                    _ => {
+                        let end = buffer[index..].iter().position(|&next| next == '\n').map_or(length, |offset| index + offset);
+                        ops.push(Op::Shell(buffer[index..end].iter().collect()));
+                        index = end;
+                        continue;
+                    }
+                };
+                index += 1;
+                let register = register(&buffer, &mut index, ch)?;
+                // Not POSIX, but BSD dc reads them as comparisons too, so they are no shell commands.
+                if dialect == Dialect::Posix {
+                    ops.push(Op::Unsupported(ch));
+                } else {
+                    ops.push(Op::Conditional(comparison, register));
+                }
+            },
+
             '_' | '0'..='9' => {
                 let mut num_str = String::new();
                 num_str.push(ch);
                 while index < length {
-                    match buffer.chars().nth(index).unwrap() {
+                    match buffer[index] {
                         '.' | '0'..='9' => {
-                            num_str.push(buffer.chars().nth(index).unwrap());
+                            num_str.push(buffer[index]);
                             index += 1;
                         },
                         _ => break,
@@ -82,18 +349,30 @@ pub fn tokenize(str: &str) -> Result<Vec<Op>, String> {
 
                 // TODO radix
                 if let Some(num) = BigInt::parse_bytes(num_str.as_bytes(), 10) {
-                    tokens.push(Op::Push(num));
+                    ops.push(Op::Push(num));
                 } else {
-                    Err(format!("Unable to parse number: {} with radix: {}", ch, 10))?;
+                    let message = format!("Unable to parse number: {} with radix: {}", ch, 10);
+                    return Err((LexErrorKind::InvalidNumber, message));
                 }
             },
-            _ => {
-                return Err(format!("Unknown operation: {}", ch))
-            }
+            _ => ops.push(Op::Unimplemented(ch)),
         };
     }
 
-    Ok(tokens)
+    let span = offsets[scanned]..offsets[index];
+    tokens.extend(ops.drain(..).map(|op| Token { op, position: *position, span: span.clone() }));
+    Ok(())
+}
+
+/// Reads the register name following a register operation.
+fn register(buffer: &[char], index: &mut usize, op: char) -> Result<char, (LexErrorKind, String)> {
+    match buffer.get(*index) {
+        Some(&name) => {
+            *index += 1;
+            Ok(name)
+        },
+        None => Err((LexErrorKind::MissingRegister, format!("Missing register name after: {}", op))),
+    }
 }
 
 #[cfg(test)]
@@ -102,66 +381,218 @@ mod test {
 
     #[test]
     fn tokenize_operation() {
-        assert_eq!(tokenize("c").unwrap(), vec![Op::Clear]);
-        assert_eq!(tokenize("d").unwrap(), vec![Op::Duplicate]);
-        assert_eq!(tokenize("r").unwrap(), vec![Op::Reverse]);
-
-        assert_eq!(tokenize("i").unwrap(), vec![Op::SetInputRadix]);
-        assert_eq!(tokenize("o").unwrap(), vec![Op::SetOutputRadix]);
-        assert_eq!(tokenize("k").unwrap(), vec![Op::SetPrecision]);
-        assert_eq!(tokenize("I").unwrap(), vec![Op::GetInputRadix]);
-        assert_eq!(tokenize("O").unwrap(), vec![Op::GetOutputRadix]);
-        assert_eq!(tokenize("K").unwrap(), vec![Op::GetPrecision]);
-
-        assert_eq!(tokenize("q").unwrap(), vec![Op::Exit]);
-        assert_eq!(tokenize("p").unwrap(), vec![Op::PrintPeek]);
-        assert_eq!(tokenize("n").unwrap(), vec![Op::PrintPop]);
-        assert_eq!(tokenize("f").unwrap(), vec![Op::PrintAll]);
-        assert_eq!(tokenize("+").unwrap(), vec![Op::Add]);
-        assert_eq!(tokenize("-").unwrap(), vec![Op::Sub]);
-        assert_eq!(tokenize("*").unwrap(), vec![Op::Mul]);
-        assert_eq!(tokenize("/").unwrap(), vec![Op::Div]);
-        assert_eq!(tokenize("%").unwrap(), vec![Op::Mod]);
-        assert_eq!(tokenize("~").unwrap(), vec![Op::DivRem]);
-        assert_eq!(tokenize("^").unwrap(), vec![Op::Exp]);
-        assert_eq!(tokenize("v").unwrap(), vec![Op::Sqrt]);
-        assert_eq!(tokenize("|").unwrap(), vec![Op::ModExp]);
+        assert_eq!(tokenize("c", Dialect::Extended).unwrap(), vec![Op::Clear]);
+        assert_eq!(tokenize("d", Dialect::Extended).unwrap(), vec![Op::Duplicate]);
+        assert_eq!(tokenize("r", Dialect::Extended).unwrap(), vec![Op::Reverse]);
+        assert_eq!(tokenize("R", Dialect::Extended).unwrap(), vec![Op::Rotate]);
+        assert_eq!(tokenize("t", Dialect::Extended).unwrap(), vec![Op::Drop]);
+
+        assert_eq!(tokenize("i", Dialect::Extended).unwrap(), vec![Op::SetInputRadix]);
+        assert_eq!(tokenize("o", Dialect::Extended).unwrap(), vec![Op::SetOutputRadix]);
+        assert_eq!(tokenize("k", Dialect::Extended).unwrap(), vec![Op::SetPrecision]);
+        assert_eq!(tokenize("I", Dialect::Extended).unwrap(), vec![Op::GetInputRadix]);
+        assert_eq!(tokenize("O", Dialect::Extended).unwrap(), vec![Op::GetOutputRadix]);
+        assert_eq!(tokenize("K", Dialect::Extended).unwrap(), vec![Op::GetPrecision]);
+
+        assert_eq!(tokenize("q", Dialect::Extended).unwrap(), vec![Op::Exit]);
+        assert_eq!(tokenize("p", Dialect::Extended).unwrap(), vec![Op::PrintPeek]);
+        assert_eq!(tokenize("n", Dialect::Extended).unwrap(), vec![Op::PrintPop]);
+        assert_eq!(tokenize("f", Dialect::Extended).unwrap(), vec![Op::PrintAll]);
+        assert_eq!(tokenize("+", Dialect::Extended).unwrap(), vec![Op::Add]);
+        assert_eq!(tokenize("-", Dialect::Extended).unwrap(), vec![Op::Sub]);
+        assert_eq!(tokenize("*", Dialect::Extended).unwrap(), vec![Op::Mul]);
+        assert_eq!(tokenize("/", Dialect::Extended).unwrap(), vec![Op::Div]);
+        assert_eq!(tokenize("%", Dialect::Extended).unwrap(), vec![Op::Mod]);
+        assert_eq!(tokenize("~", Dialect::Extended).unwrap(), vec![Op::DivRem]);
+        assert_eq!(tokenize("^", Dialect::Extended).unwrap(), vec![Op::Exp]);
+        assert_eq!(tokenize("v", Dialect::Extended).unwrap(), vec![Op::Sqrt]);
+        assert_eq!(tokenize("|", Dialect::Extended).unwrap(), vec![Op::ModExp]);
+    }
+
+    #[test]
+    fn display_operation() {
+        let source = "_42 [a b] sa La :b !<c p + |";
+        let ops = tokenize(source, Dialect::Extended).unwrap();
+        let rendered = ops.iter().map(|op| op.to_string()).collect::<Vec<String>>().join(" ");
+        assert_eq!(rendered, source);
+        assert_eq!(tokenize(&rendered, Dialect::Extended).unwrap(), ops);
     }
 
     #[test]
     fn tokenize_zero() {
-        assert_eq!(tokenize("0").unwrap(), vec![Op::Push(BigInt::from(0))]);
+        assert_eq!(tokenize("0", Dialect::Extended).unwrap(), vec![Op::Push(BigInt::from(0))]);
     }
 
     #[test]
     fn tokenize_number() {
-        assert_eq!(tokenize("42").unwrap(), vec![Op::Push(BigInt::from(42))]);
+        assert_eq!(tokenize("42", Dialect::Extended).unwrap(), vec![Op::Push(BigInt::from(42))]);
     }
 
     #[test]
     fn tokenize_negative_number() {
-        assert_eq!(tokenize("_42").unwrap(), vec![Op::Push(BigInt::from(-42))]);
+        assert_eq!(tokenize("_42", Dialect::Extended).unwrap(), vec![Op::Push(BigInt::from(-42))]);
     }
 
     // TODO
     // #[test]
     // fn tokenize_floating_number() {
-    //     assert_eq!(tokenize("3.1415").unwrap(), vec![Op::Push(BigInt::from(3.1415))]);
+    //     assert_eq!(tokenize("3.1415", Dialect::Extended).unwrap(), vec![Op::Push(BigInt::from(3.1415))]);
     // }
     //
     // #[test]
     // fn tokenize_negative_floating_number() {
-    //     assert_eq!(tokenize("_3.1415").unwrap(), vec![Op::Push(BigInt::from(-3.1415))]);
+    //     assert_eq!(tokenize("_3.1415", Dialect::Extended).unwrap(), vec![Op::Push(BigInt::from(-3.1415))]);
     // }
 
     #[test]
     fn tokenize_leading_zero_number() {
-        assert_eq!(tokenize("04").unwrap(), vec![Op::Push(BigInt::from(4))]);
+        assert_eq!(tokenize("04", Dialect::Extended).unwrap(), vec![Op::Push(BigInt::from(4))]);
     }
 
     #[test]
     fn tokenize_unknown_operation() {
-        assert_eq!(tokenize("x"), Err("Unknown operation: x".to_owned()));
+        assert_eq!(tokenize("y", Dialect::Extended), Ok(vec![Op::Unimplemented('y')]));
+    }
+
+    #[test]
+    fn tokenize_unknown_operation_continues() {
+        let expected = vec![
+            Op::Push(BigInt::from(5)),
+            Op::Unimplemented('y'),
+            Op::Push(BigInt::from(3)),
+            Op::Unimplemented('!'),
+            Op::PrintPeek
+        ];
+        assert_eq!(tokenize("5y 3!p", Dialect::Extended), Ok(expected));
+    }
+
+    #[test]
+    fn tokenize_dialect() {
+        let expected = vec![
+            Op::Push(BigInt::from(1)),
+            Op::Unsupported('r'),
+            Op::Unsupported('R'),
+            Op::Unsupported('~'),
+            Op::Unsupported('|'),
+            Op::Unsupported('n'),
+            Op::Unsupported('t'),
+            Op::PushString("r~".to_owned()),
+            Op::Store('r')
+        ];
+        assert_eq!(tokenize("1rR~|nt[r~]sr", Dialect::Posix), Ok(expected));
+        assert_eq!(tokenize("rR~|n", Dialect::Gnu), tokenize("rR~|n", Dialect::Extended));
+        assert_eq!(tokenize("t", Dialect::Gnu), Ok(vec![Op::Unsupported('t')]));
+    }
+
+    #[test]
+    fn tokenize_bang() {
+        let negated = Ok(vec![Op::Conditional(Comparison::NotLess, 'a'), Op::Unimplemented('!'), Op::PrintPeek]);
+        assert_eq!(tokenize("!<a !p", Dialect::Extended), negated);
+        assert_eq!(
+            tokenize("!<a !echo 1\np", Dialect::Gnu),
+            Ok(vec![Op::Conditional(Comparison::NotLess, 'a'), Op::Shell("echo 1".to_owned()), Op::PrintPeek])
+        );
+        assert_eq!(
+            tokenize("1 !<a !=b !>c !echo 1\n", Dialect::Posix),
+            Ok(vec![
+                Op::Push(BigInt::from(1)),
+                Op::Unsupported('!'),
+                Op::Unsupported('!'),
+                Op::Unsupported('!'),
+                Op::Shell("echo 1".to_owned())
+            ])
+        );
+    }
+
+    #[test]
+    fn tokenize_whitespace() {
+        assert_eq!(tokenize(" 1\t2\n+ ", Dialect::Extended), Ok(vec![
+            Op::Push(BigInt::from(1)),
+            Op::Push(BigInt::from(2)),
+            Op::Add
+        ]));
+    }
+
+    #[test]
+    fn tokenize_comment() {
+        assert_eq!(tokenize("#!/usr/bin/dc -f\n1 # one\n2", Dialect::Extended), Ok(vec![
+            Op::Push(BigInt::from(1)),
+            Op::Push(BigInt::from(2))
+        ]));
+        assert_eq!(tokenize("[#]", Dialect::Extended), Ok(vec![Op::PushString("#".to_owned())]));
+    }
+
+    #[test]
+    fn lex_positions() {
+        let tokens = lex("1 2\n [a\nb]x\n\n  sa", Dialect::Extended).unwrap();
+        let positions = tokens.iter()
+            .map(|token| (token.position.line, token.position.column))
+            .collect::<Vec<(usize, usize)>>();
+        assert_eq!(positions, vec![(1, 1), (1, 3), (2, 2), (3, 3), (5, 3)]);
+    }
+
+    #[test]
+    fn lex_spans() {
+        let source = "1 é\n [a\nb]x sa";
+        let spans = lex(source, Dialect::Extended)
+            .unwrap()
+            .into_iter()
+            .map(|token| &source[token.span])
+            .collect::<Vec<&str>>();
+        assert_eq!(spans, vec!["1", "é", "[a\nb]", "x", "sa"]);
+    }
+
+    #[test]
+    fn lex_partial_error() {
+        let (tokens, err) = lex_partial("1 lé [2 [3", Dialect::Extended);
+        assert_eq!(tokens.iter().map(|token| token.op.clone()).collect::<Vec<Op>>(), vec![Op::Push(BigInt::from(1)), Op::Load('é')]);
+        let err = err.unwrap();
+        assert_eq!((err.kind, err.span), (LexErrorKind::UnterminatedString, 6..11));
+        assert_eq!(err.message, "Unterminated string: [2 [3");
+    }
+
+    #[test]
+    fn lex_error_position() {
+        let err = lex("1\n  2 [3", Dialect::Extended).unwrap_err();
+        assert_eq!(err.position, Position { line: 2, column: 5 });
+        assert_eq!(err.message, "Unterminated string: [3");
+        assert_eq!(lex_partial("1 s", Dialect::Extended).1.map(|err| err.kind), Some(LexErrorKind::MissingRegister));
+    }
+
+    #[test]
+    fn tokenize_string() {
+        assert_eq!(tokenize("[1 2+]", Dialect::Extended), Ok(vec![Op::PushString("1 2+".to_owned())]));
+        assert_eq!(tokenize("[a[b]c]", Dialect::Extended), Ok(vec![Op::PushString("a[b]c".to_owned())]));
+        assert_eq!(tokenize("[]", Dialect::Extended), Ok(vec![Op::PushString(String::new())]));
+    }
+
+    #[test]
+    fn tokenize_unterminated_string() {
+        assert_eq!(tokenize("[1 2", Dialect::Extended), Err("Unterminated string: [1 2".to_owned()));
+    }
+
+    #[test]
+    fn tokenize_registers() {
+        assert_eq!(tokenize("sa", Dialect::Extended), Ok(vec![Op::Store('a')]));
+        assert_eq!(tokenize("la", Dialect::Extended), Ok(vec![Op::Load('a')]));
+        assert_eq!(tokenize("Sa", Dialect::Extended), Ok(vec![Op::PushRegister('a')]));
+        assert_eq!(tokenize("La", Dialect::Extended), Ok(vec![Op::PopRegister('a')]));
+        assert_eq!(tokenize(":a", Dialect::Extended), Ok(vec![Op::StoreArray('a')]));
+        assert_eq!(tokenize(";a", Dialect::Extended), Ok(vec![Op::LoadArray('a')]));
+        assert_eq!(tokenize("z", Dialect::Extended), Ok(vec![Op::StackDepth]));
+        assert_eq!(tokenize("s", Dialect::Extended), Err("Missing register name after: s".to_owned()));
+    }
+
+    #[test]
+    fn tokenize_conditionals() {
+        assert_eq!(tokenize("x", Dialect::Extended), Ok(vec![Op::Execute]));
+        assert_eq!(tokenize("?", Dialect::Posix), Ok(vec![Op::Read]));
+        assert_eq!(tokenize("<a", Dialect::Extended), Ok(vec![Op::Conditional(Comparison::Less, 'a')]));
+        assert_eq!(tokenize(">a", Dialect::Extended), Ok(vec![Op::Conditional(Comparison::Greater, 'a')]));
+        assert_eq!(tokenize("=a", Dialect::Extended), Ok(vec![Op::Conditional(Comparison::Equal, 'a')]));
+        assert_eq!(tokenize("!<a", Dialect::Extended), Ok(vec![Op::Conditional(Comparison::NotLess, 'a')]));
+        assert_eq!(tokenize("!>a", Dialect::Extended), Ok(vec![Op::Conditional(Comparison::NotGreater, 'a')]));
+        assert_eq!(tokenize("!=a", Dialect::Extended), Ok(vec![Op::Conditional(Comparison::NotEqual, 'a')]));
     }
 
     #[test]
@@ -173,6 +604,6 @@ mod test {
             Op::PrintPeek,
             Op::Exit
         ];
-        assert_eq!(tokenize("42d*pq"), Ok(expected));
+        assert_eq!(tokenize("42d*pq", Dialect::Extended), Ok(expected));
     }
 }
\ No newline at end of file
@@ -0,0 +1,229 @@
+use dc::calculator::{Calculator, Diagnostic, OpResult};
+use dc::output::Output;
+use dc::{process_input, process_ops, Op};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph};
+use ratatui::Frame;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io;
+use std::rc::Rc;
+
+const HELP: &str = "Enter: run or dup  Backspace: drop  Tab: swap  ^Z/^Y: undo/redo  PgUp/PgDn: scroll  Esc: quit";
+
+/// The number of message lines shown, and kept.
+const MESSAGES: usize = 5;
+
+/// The state of the full-screen calculator besides the calculator itself.
+struct Screen {
+    input: String,
+    /// The number of stack values hidden below the bottom of the stack panel.
+    scroll: usize,
+    /// The output of the printing commands and the errors of the last action.
+    messages: Vec<String>,
+}
+
+/// Keeps the last lines the calculator printed or reported, for the screen to take after every
+/// action. Older lines are dropped, so long running loops do not fill the memory.
+#[derive(Clone, Default)]
+struct Log(Rc<RefCell<Lines>>);
+
+#[derive(Default)]
+struct Lines {
+    lines: VecDeque<String>,
+    /// Whether the last line still waits for its line break.
+    open: bool,
+}
+
+impl Log {
+    fn push(&self, text: &str, open: bool) {
+        let log = &mut *self.0.borrow_mut();
+        match log.lines.back_mut() {
+            Some(last) if log.open => last.push_str(text),
+            _ => {
+                if log.lines.len() == MESSAGES {
+                    log.lines.pop_front();
+                }
+                log.lines.push_back(text.to_owned());
+            }
+        }
+        log.open = open;
+    }
+
+    fn take(&self) -> Vec<String> {
+        let mut log = self.0.borrow_mut();
+        log.open = false;
+        log.lines.drain(..).collect()
+    }
+}
+
+impl Output for Log {
+    fn print(&mut self, text: &str) -> io::Result<()> {
+        for line in text.split_inclusive('\n') {
+            self.push(line.trim_end_matches('\n'), !line.ends_with('\n'));
+        }
+        Ok(())
+    }
+
+    fn report(&mut self, diagnostic: &Diagnostic) -> io::Result<()> {
+        self.0.borrow_mut().open = false;
+        self.push(&format!("dc: {}", diagnostic.message), false);
+        Ok(())
+    }
+}
+
+/// Runs the full-screen stack calculator until the user quits or a command exits.
+pub fn run(calculator: &mut Calculator) -> io::Result<OpResult> {
+    let mut terminal = ratatui::try_init()?;
+    let log = Log::default();
+    calculator.set_output(Box::new(log.clone()));
+    let mut screen = Screen { input: String::new(), scroll: 0, messages: Vec::new() };
+
+    let result = loop {
+        if let Err(err) = terminal.draw(|frame| draw(frame, calculator, &screen)) {
+            break Err(err);
+        }
+
+        let key = match event::read() {
+            Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => key,
+            Ok(_) => continue,
+            Err(err) => break Err(err),
+        };
+
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let outcome = match key.code {
+            KeyCode::Esc => break Ok(OpResult::Ok),
+            KeyCode::Char('c') | KeyCode::Char('d') if ctrl => break Ok(OpResult::Ok),
+            KeyCode::Char('z') if ctrl => {
+                screen.messages = if calculator.undo() { vec![] } else { vec!["nothing to undo".to_owned()] };
+                screen.scroll = 0;
+                OpResult::Ok
+            }
+            KeyCode::Char('y') if ctrl => {
+                screen.messages = if calculator.redo() { vec![] } else { vec!["nothing to redo".to_owned()] };
+                screen.scroll = 0;
+                OpResult::Ok
+            }
+            KeyCode::Enter if screen.input.trim().is_empty() => act(calculator, &mut screen, &log, |calculator| {
+                process_ops(calculator, vec![Op::Duplicate])
+            }),
+            KeyCode::Enter => {
+                let input = std::mem::take(&mut screen.input);
+                act(calculator, &mut screen, &log, |calculator| process_input(calculator, &input))
+            }
+            KeyCode::Backspace if screen.input.is_empty() => {
+                act(calculator, &mut screen, &log, |calculator| process_ops(calculator, vec![Op::Drop]))
+            }
+            KeyCode::Backspace => {
+                screen.input.pop();
+                OpResult::Ok
+            }
+            KeyCode::Tab => act(calculator, &mut screen, &log, |calculator| process_ops(calculator, vec![Op::Reverse])),
+            KeyCode::PageUp => {
+                screen.scroll = (screen.scroll + 1).min(calculator.iter().count().saturating_sub(1));
+                OpResult::Ok
+            }
+            KeyCode::PageDown => {
+                screen.scroll = screen.scroll.saturating_sub(1);
+                OpResult::Ok
+            }
+            KeyCode::Char(ch) if !ctrl => {
+                screen.input.push(ch);
+                OpResult::Ok
+            }
+            _ => OpResult::Ok,
+        };
+
+        if outcome == OpResult::Exit {
+            break Ok(OpResult::Exit);
+        }
+    };
+
+    ratatui::restore();
+    result
+}
+
+/// Runs an action on the calculator, and keeps what it printed and reported as the messages.
+fn act<F>(calculator: &mut Calculator, screen: &mut Screen, log: &Log, action: F) -> OpResult
+where
+    F: FnOnce(&mut Calculator) -> Result<OpResult, String>,
+{
+    calculator.checkpoint();
+    let result = action(calculator);
+
+    screen.messages = log.take();
+    screen.scroll = 0;
+
+    result.unwrap_or_else(|err| {
+        screen.messages.push(format!("Error: {}", err));
+        OpResult::Ok
+    })
+}
+
+fn draw(frame: &mut Frame, calculator: &Calculator, screen: &Screen) {
+    let [main, messages, input, help] = Layout::vertical([
+        Constraint::Min(3),
+        Constraint::Length(screen.messages.len().min(MESSAGES) as u16 + 2),
+        Constraint::Length(3),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+    let [stack, side] = Layout::horizontal([Constraint::Min(20), Constraint::Length(32)]).areas(main);
+    let [registers, settings] = Layout::vertical([Constraint::Min(3), Constraint::Length(5)]).areas(side);
+
+    draw_stack(frame, stack, calculator, screen.scroll);
+    frame.render_widget(
+        Paragraph::new(register_lines(calculator)).block(Block::bordered().title(" Registers ")),
+        registers,
+    );
+    let setting_lines = vec![
+        Line::from(format!("input radix  {}", calculator.input_radix())),
+        Line::from(format!("output radix {}", calculator.output_radix())),
+        Line::from(format!("precision    {}", calculator.precision())),
+    ];
+    frame.render_widget(Paragraph::new(setting_lines).block(Block::bordered().title(" Settings ")), settings);
+
+    let message_lines = screen.messages.iter().rev().take(MESSAGES).rev().map(|message| Line::from(message.as_str()));
+    frame.render_widget(Paragraph::new(message_lines.collect::<Vec<Line>>()).block(Block::bordered()), messages);
+    frame.render_widget(Paragraph::new(format!("> {}", screen.input)).block(Block::bordered()), input);
+    frame.render_widget(Paragraph::new(HELP).style(Style::default().add_modifier(Modifier::DIM)), help);
+    frame.set_cursor_position((input.x + 3 + screen.input.chars().count() as u16, input.y + 1));
+}
+
+/// Shows the stack with the top at the bottom of the panel, labelled 1 like on an RPN calculator.
+fn draw_stack(frame: &mut Frame, area: Rect, calculator: &Calculator, scroll: usize) {
+    let height = area.height.saturating_sub(2) as usize;
+    let depth = calculator.iter().count();
+    let shown = depth.saturating_sub(scroll);
+    let lines = calculator
+        .iter()
+        .enumerate()
+        .map(|(index, value)| Line::from(format!("{:>3}: {}", depth - index, value.literal())))
+        .take(shown)
+        .collect::<Vec<Line>>();
+    let padding = height.saturating_sub(lines.len());
+    let visible = std::iter::repeat_n(Line::from(""), padding)
+        .chain(lines.into_iter().skip(shown.saturating_sub(height)))
+        .collect::<Vec<Line>>();
+
+    frame.render_widget(Paragraph::new(visible).block(Block::bordered().title(" Stack ")), area);
+}
+
+fn register_lines(calculator: &Calculator) -> Vec<Line<'static>> {
+    calculator
+        .register_names()
+        .into_iter()
+        .map(|name| {
+            let top = calculator.register_stack(name).last().map_or("-".to_owned(), |value| value.literal());
+            let elements = calculator.register_array(name).len();
+            if elements > 0 {
+                Line::from(format!("{} {} [{} elements]", name, top, elements))
+            } else {
+                Line::from(format!("{} {}", name, top))
+            }
+        })
+        .collect()
+}